@@ -0,0 +1,685 @@
+// Assembler for the marz assembly syntax (see marz/all.asm for every form it accepts)
+//
+// Assembly happens in two passes. The first pass parses every line, records label
+// addresses and works out how many words each statement occupies. The second pass
+// encodes each statement now that every label has a known address.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::loader::{self, Image, Section, SectionKind};
+use crate::symbols::Symbols;
+use crate::MAGIC;
+
+/// Images are padded with `nop` so the code is always a multiple of this many words
+const ALIGN_WORDS: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl AsmError {
+    fn new(line: usize, msg: impl Into<String>) -> Self {
+        AsmError {
+            line,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug)]
+enum Arg {
+    Num(i64),
+    Label(String),
+    Str(String),
+}
+
+struct Statement {
+    line: usize,
+    mnemonic: String,
+    args: Vec<Arg>,
+    // Word index of the first word this statement occupies
+    addr: usize,
+}
+
+/// Assembles `source` into a program image, starting with the magic word.
+/// The result can be passed straight to `Machine::load`.
+///
+/// Sources that use `.entry <label>` or `.data` produce an image with a header;
+/// everything else produces a legacy headerless image.
+pub fn assemble(source: &str) -> Result<Vec<u32>, AsmError> {
+    assemble_with_symbols(source).map(|(program, _)| program)
+}
+
+/// Like `assemble`, but also returns the labels, for debuggers and other tools
+/// that want to show names instead of addresses
+pub fn assemble_with_symbols(source: &str) -> Result<(Vec<u32>, Symbols), AsmError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut statements = Vec::new();
+    let mut addr: usize = 0;
+    // Line and label of `.entry`, and the word index `.data` starts at
+    let mut entry: Option<(usize, String)> = None;
+    let mut data_start: Option<usize> = None;
+
+    // Pass 1: parse, lay out and collect labels
+    for (i, raw) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut rest = strip_comment(raw).trim();
+
+        let label = split_label(rest).map(|(label, after)| {
+            rest = after.trim();
+            label
+        });
+
+        // The data section starts after the padded code, so a label on the same
+        // line points at the first data word
+        if rest == ".data" {
+            if data_start.is_some() {
+                return Err(AsmError::new(line, "duplicate '.data'"));
+            }
+            addr = addr.next_multiple_of(ALIGN_WORDS);
+            data_start = Some(addr);
+            rest = "";
+        }
+
+        if let Some(label) = label {
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(AsmError::new(line, format!("duplicate label '{}'", label)));
+            }
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, args) = parse_statement(line, rest)?;
+        if mnemonic == ".entry" {
+            match args.as_slice() {
+                [Arg::Label(name)] if entry.is_none() => entry = Some((line, name.clone())),
+                [Arg::Label(_)] => return Err(AsmError::new(line, "duplicate '.entry'")),
+                _ => return Err(AsmError::new(line, "'.entry' expects a label")),
+            }
+            continue;
+        }
+
+        let size = statement_size(line, &mnemonic, &args)?;
+        statements.push(Statement {
+            line,
+            mnemonic,
+            args,
+            addr,
+        });
+        addr += size;
+    }
+
+    // Pass 2: encode
+    let mut words = Vec::new();
+    for statement in &statements {
+        // Only `.data` leaves a gap, which is filled like the end of the code
+        words.resize(statement.addr, Instruction::Nop().encode());
+        encode_statement(statement, &labels, &mut words)?;
+    }
+
+    let code_len = data_start
+        .unwrap_or(words.len())
+        .next_multiple_of(ALIGN_WORDS);
+    words.resize(words.len().max(code_len), Instruction::Nop().encode());
+    let data = words.split_off(code_len);
+
+    let entry = match entry {
+        Some((line, name)) => match labels.get(&name) {
+            Some(&addr) if addr < code_len => Some(addr),
+            Some(_) => return Err(AsmError::new(line, "'.entry' must point into code")),
+            None => return Err(AsmError::new(line, format!("undefined label '{}'", name))),
+        },
+        None => None,
+    };
+
+    if entry.is_none() && data_start.is_none() {
+        let mut program = vec![MAGIC];
+        program.extend(words);
+        return Ok((program, symbols(labels, &statements)));
+    }
+
+    let mut sections = vec![Section {
+        kind: SectionKind::Code,
+        addr: 0,
+        words,
+    }];
+    if data_start.is_some() {
+        sections.push(Section {
+            kind: SectionKind::Data,
+            addr: code_len,
+            words: data,
+        });
+    }
+
+    let image = Image {
+        version: loader::VERSION,
+        flags: 0,
+        entry: entry.unwrap_or(0),
+        sections,
+    };
+    Ok((image.to_words(), symbols(labels, &statements)))
+}
+
+fn symbols(labels: HashMap<String, usize>, statements: &[Statement]) -> Symbols {
+    Symbols {
+        labels: labels.into_iter().collect(),
+        lines: statements.iter().map(|s| (s.addr, s.line)).collect(),
+        file: None,
+    }
+}
+
+/// Serializes a program (as produced by `assemble`) into the bytes of a .v file
+pub fn to_bytes(program: &[u32]) -> Vec<u8> {
+    program.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
+
+// Removes a trailing `#` comment, ignoring any `#` inside a string literal
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == '#' {
+            return &line[..i];
+        }
+    }
+
+    line
+}
+
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let end = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(line.len());
+
+    if end > 0 && line[end..].starts_with(':') {
+        Some((&line[..end], &line[end + 1..]))
+    } else {
+        None
+    }
+}
+
+fn parse_statement(line: usize, text: &str) -> Result<(String, Vec<Arg>), AsmError> {
+    let (mnemonic, mut rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim_start()),
+        None => (text, ""),
+    };
+
+    let mut args = Vec::new();
+    while !rest.is_empty() {
+        if let Some(body) = rest.strip_prefix('"') {
+            let (s, consumed) = parse_string(line, body)?;
+            args.push(Arg::Str(s));
+            rest = body[consumed..].trim_start();
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            args.push(parse_operand(line, &rest[..end])?);
+            rest = rest[end..].trim_start();
+        }
+    }
+
+    Ok((mnemonic.to_ascii_lowercase(), args))
+}
+
+// Parses the body of a string literal (after the opening quote). Returns the
+// unescaped string and the number of bytes consumed, including the closing quote.
+fn parse_string(line: usize, body: &str) -> Result<(String, usize), AsmError> {
+    let mut s = String::new();
+    let mut chars = body.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((s, i + 1)),
+            '\\' => match chars.next() {
+                Some((_, '\\')) => s.push('\\'),
+                Some((_, 'n')) => s.push('\n'),
+                Some((_, '"')) => s.push('"'),
+                Some((_, other)) => {
+                    return Err(AsmError::new(line, format!("unknown escape '\\{}'", other)))
+                }
+                None => break,
+            },
+            _ => s.push(c),
+        }
+    }
+
+    Err(AsmError::new(line, "unterminated string literal"))
+}
+
+fn parse_operand(line: usize, text: &str) -> Result<Arg, AsmError> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, text),
+    };
+
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse::<i64>().ok()
+    } else if !negative && !text.is_empty() {
+        return Ok(Arg::Label(text.to_string()));
+    } else {
+        None
+    };
+
+    match parsed {
+        Some(n) => Ok(Arg::Num(if negative { -n } else { n })),
+        None => Err(AsmError::new(line, format!("invalid operand '{}'", text))),
+    }
+}
+
+// Number of words a statement assembles to
+fn statement_size(line: usize, mnemonic: &str, args: &[Arg]) -> Result<usize, AsmError> {
+    if mnemonic != "stpush" {
+        return Ok(1);
+    }
+
+    match args {
+        [Arg::Str(s)] => Ok(stpush_words(s).len()),
+        _ => Err(AsmError::new(
+            line,
+            "stpush expects a single string literal",
+        )),
+    }
+}
+
+/// Encodes a string as the sequence of `push` words produced by `stpush`, in the
+/// order they are pushed. The string is split into groups of three characters
+/// (the last one padded with 0x01), and the groups are pushed last to first so the
+/// start of the string ends up on top of the stack. Every group except the final
+/// one has bit 24 set to mark that the string continues in the next word.
+pub fn stpush_words(s: &str) -> Vec<u32> {
+    let bytes = s.as_bytes();
+    if bytes.is_empty() {
+        return vec![Instruction::Push(0).encode()];
+    }
+
+    let chunks: Vec<&[u8]> = bytes.chunks(3).collect();
+    let last = chunks.len() - 1;
+
+    chunks
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, chunk)| {
+            let mut value = 0;
+            for j in 0..3 {
+                let byte = chunk.get(j).copied().unwrap_or(1);
+                value |= (byte as u32) << (8 * j);
+            }
+            if i != last {
+                value |= 1 << 24;
+            }
+            Instruction::Push(value).encode()
+        })
+        .collect()
+}
+
+// Maximum number of arguments each mnemonic accepts, or None if it isn't one
+fn max_args(mnemonic: &str) -> Option<usize> {
+    match mnemonic {
+        "swap" => Some(2),
+        "exit" | "stinput" | "debug" | "pop" | "stprint" | "call" | "return" | "goto" | "ifeq"
+        | "ifne" | "iflt" | "ifgt" | "ifle" | "ifge" | "ifez" | "ifnz" | "ifmi" | "ifpl"
+        | "dup" | "print" | "printh" | "printb" | "printo" | "push" | "stpush" | ".word"
+        | ".entry" => Some(1),
+        "nop" | "input" | "add" | "sub" | "mul" | "div" | "rem" | "and" | "or" | "xor" | "lsl"
+        | "lsr" | "asr" | "neg" | "not" | "dump" => Some(0),
+        _ => None,
+    }
+}
+
+fn encode_statement(
+    statement: &Statement,
+    labels: &HashMap<String, usize>,
+    program: &mut Vec<u32>,
+) -> Result<(), AsmError> {
+    let line = statement.line;
+    let args = &statement.args;
+
+    match max_args(&statement.mnemonic) {
+        None => {
+            return Err(AsmError::new(
+                line,
+                format!("unknown instruction '{}'", statement.mnemonic),
+            ))
+        }
+        Some(max) if args.len() > max => {
+            return Err(AsmError::new(
+                line,
+                format!("too many arguments for '{}'", statement.mnemonic),
+            ))
+        }
+        _ => (),
+    }
+
+    // Numeric argument `i`, or `default` if it was omitted
+    let num = |i: usize, default: i64| -> Result<i64, AsmError> {
+        match args.get(i) {
+            None => Ok(default),
+            Some(Arg::Num(n)) => Ok(*n),
+            Some(other) => Err(AsmError::new(
+                line,
+                format!("expected a number, got {:?}", other),
+            )),
+        }
+    };
+
+    let label_addr = |name: &str| -> Result<usize, AsmError> {
+        labels
+            .get(name)
+            .copied()
+            .ok_or_else(|| AsmError::new(line, format!("undefined label '{}'", name)))
+    };
+
+    // PC-relative byte offset to the label given as the only argument, which has
+    // to fit in a `bits` wide field
+    let target = |bits: u32| -> Result<i32, AsmError> {
+        match args.as_slice() {
+            [Arg::Label(name)] => {
+                let offset = (label_addr(name)? as i64 - statement.addr as i64) * 4;
+                signed(line, offset, bits)
+            }
+            _ => Err(AsmError::new(
+                line,
+                format!("'{}' expects a label", statement.mnemonic),
+            )),
+        }
+    };
+
+    // Byte offset argument `i` (or `default`), which has to be a whole word
+    let offset = |i: usize, default: i64| -> Result<i64, AsmError> {
+        let n = num(i, default)?;
+        if n % 4 != 0 {
+            return Err(AsmError::new(
+                line,
+                format!("offset {} is not a multiple of 4", n),
+            ));
+        }
+        Ok(n)
+    };
+
+    let instruction = match statement.mnemonic.as_str() {
+        // Negative statuses wrap around like they do in a shell, so -1 is 255
+        "exit" => Instruction::Exit(in_range(line, num(0, 0)?, -255, 255)? as u8),
+        // Swap offsets are given in bytes but encoded in words
+        "swap" => Instruction::Swap(
+            (signed(line, offset(0, 4)?, 14)? >> 2) as i16,
+            (signed(line, offset(1, 0)?, 14)? >> 2) as i16,
+        ),
+        "nop" => Instruction::Nop(),
+        "input" => Instruction::Input(),
+        "stinput" => Instruction::Stinput(unsigned(line, num(0, 0xFF_FFFF)?, 24)?),
+        "debug" => Instruction::Debug(unsigned(line, num(0, 0)?, 24)?),
+        "pop" => Instruction::Pop(unsigned(line, offset(0, 4)?, 28)?),
+        "add" => Instruction::Add(),
+        "sub" => Instruction::Sub(),
+        "mul" => Instruction::Mul(),
+        "div" => Instruction::Div(),
+        "rem" => Instruction::Rem(),
+        "and" => Instruction::And(),
+        "or" => Instruction::Or(),
+        "xor" => Instruction::Xor(),
+        "lsl" => Instruction::Lsl(),
+        "lsr" => Instruction::Lsr(),
+        "asr" => Instruction::Asr(),
+        "neg" => Instruction::Neg(),
+        "not" => Instruction::Not(),
+        "stprint" => Instruction::Stprint(signed(line, offset(0, 0)?, 28)?),
+        "call" => Instruction::Call(target(28)?),
+        "return" => Instruction::Return(signed(line, offset(0, 0)?, 28)?),
+        "goto" => Instruction::Goto(target(28)?),
+        "ifeq" => Instruction::IfEq(target(24)?),
+        "ifne" => Instruction::IfNe(target(24)?),
+        "iflt" => Instruction::IfLt(target(24)?),
+        "ifgt" => Instruction::IfGt(target(24)?),
+        "ifle" => Instruction::IfLe(target(24)?),
+        "ifge" => Instruction::IfGe(target(24)?),
+        "ifez" => Instruction::EqZero(target(24)?),
+        "ifnz" => Instruction::NeZero(target(24)?),
+        "ifmi" => Instruction::LtZero(target(24)?),
+        "ifpl" => Instruction::GeZero(target(24)?),
+        "dup" => Instruction::Dup(signed(line, offset(0, 0)?, 28)?),
+        // Print offsets are also given in bytes but encoded in words
+        "print" => Instruction::Print(signed(line, offset(0, 0)?, 28)? >> 2, 0),
+        "printh" => Instruction::Print(signed(line, offset(0, 0)?, 28)? >> 2, 1),
+        "printb" => Instruction::Print(signed(line, offset(0, 0)?, 28)? >> 2, 2),
+        "printo" => Instruction::Print(signed(line, offset(0, 0)?, 28)? >> 2, 3),
+        "dump" => Instruction::Dump(),
+        "push" => {
+            let value = match args.first() {
+                Some(Arg::Label(name)) => label_addr(name)? as i64 * 4,
+                _ => num(0, 0)?,
+            };
+            Instruction::Push(signed(line, value, 28)? as u32)
+        }
+        "stpush" => {
+            // Already validated when the statement was sized
+            if let [Arg::Str(s)] = args.as_slice() {
+                program.extend(stpush_words(s));
+            }
+            return Ok(());
+        }
+        ".word" => {
+            // Raw word, emitted by the disassembler for words it can't write as an
+            // instruction
+            match args.first() {
+                Some(Arg::Num(n)) => {
+                    program.push(in_range(line, *n, i32::MIN as i64, u32::MAX as i64)? as u32)
+                }
+                _ => return Err(AsmError::new(line, "'.word' expects a number")),
+            }
+            return Ok(());
+        }
+        _ => unreachable!("max_args accepted an unknown mnemonic"),
+    };
+
+    program.push(instruction.encode());
+    Ok(())
+}
+
+// Checks that an operand is between `min` and `max`, inclusive
+fn in_range(line: usize, value: i64, min: i64, max: i64) -> Result<i64, AsmError> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(AsmError::new(
+            line,
+            format!("{} is out of range ({} to {})", value, min, max),
+        ))
+    }
+}
+
+// Checks that an operand fits an unsigned field `bits` wide
+fn unsigned(line: usize, value: i64, bits: u32) -> Result<u32, AsmError> {
+    in_range(line, value, 0, (1 << bits) - 1).map(|n| n as u32)
+}
+
+// Checks that an operand fits a two's complement field `bits` wide
+fn signed(line: usize, value: i64, bits: u32) -> Result<i32, AsmError> {
+    in_range(line, value, -1 << (bits - 1), (1 << (bits - 1)) - 1).map(|n| n as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks(4)
+            .map(|x| u32::from_le_bytes(<[u8; 4]>::try_from(x).unwrap()))
+            .collect()
+    }
+
+    macro_rules! marz_fixture {
+        ($name:ident, $file:literal) => {
+            #[test]
+            fn $name() {
+                let source = include_str!(concat!("../marz/", $file, ".asm"));
+                let expected = include_bytes!(concat!("../marz/", $file, ".v"));
+
+                let program = assemble(source).unwrap();
+
+                assert_eq!(words(expected), program);
+                assert_eq!(&expected[..], &to_bytes(&program)[..]);
+            }
+        };
+    }
+
+    marz_fixture!(marz_abs, "abs");
+    marz_fixture!(marz_add, "add");
+    marz_fixture!(marz_all, "all");
+    marz_fixture!(marz_avg, "avg");
+    marz_fixture!(marz_calc, "calc");
+    marz_fixture!(marz_call, "call");
+    marz_fixture!(marz_debug, "debug");
+    marz_fixture!(marz_for, "for");
+    marz_fixture!(marz_print, "print");
+    marz_fixture!(marz_sign, "sign");
+    marz_fixture!(marz_stinput, "stinput");
+    marz_fixture!(marz_str, "str");
+    marz_fixture!(marz_sum, "sum");
+    marz_fixture!(marz_swap, "swap");
+    marz_fixture!(marz_twoc, "twoc");
+
+    #[test]
+    fn test_undefined_label() {
+        let err = assemble("main:\n    goto nowhere\n").unwrap_err();
+        assert_eq!(2, err.line);
+    }
+
+    #[test]
+    fn test_unknown_escape() {
+        assert!(assemble("stpush \"bad \\t escape\"").is_err());
+    }
+
+    #[test]
+    fn test_operand_range() {
+        let error = |source: &str| assemble(source).unwrap_err();
+
+        assert_eq!(
+            AsmError::new(2, "300 is out of range (-255 to 255)"),
+            error("nop\nexit 300")
+        );
+        assert_eq!(
+            AsmError::new(1, "offset 6 is not a multiple of 4"),
+            error("swap 6 0")
+        );
+        assert_eq!(
+            AsmError::new(1, "-8196 is out of range (-8192 to 8191)"),
+            error("swap 4 -8196")
+        );
+        assert!(error("stinput 0x1000000").msg.contains("out of range"));
+        assert!(error("debug -1").msg.contains("out of range"));
+        assert!(error("pop -4").msg.contains("out of range"));
+        assert!(error("pop 2").msg.contains("multiple of 4"));
+        assert!(error("stprint 0x8000000").msg.contains("out of range"));
+        assert!(error("return 0x8000000").msg.contains("out of range"));
+        assert!(error("dup 3").msg.contains("multiple of 4"));
+        assert!(error("print 0x8000000").msg.contains("out of range"));
+        assert!(error("printh 1").msg.contains("multiple of 4"));
+        assert!(error("push 200000000").msg.contains("out of range"));
+        assert!(error("push -2147483648").msg.contains("out of range"));
+        assert!(error(".word 0x100000000").msg.contains("out of range"));
+
+        // Only the ends of the push range are representable
+        assert!(assemble("push 0x7ffffff\npush -0x8000000").is_ok());
+        assert!(assemble(".word -1\n.word 0xffffffff").is_ok());
+    }
+
+    #[test]
+    fn test_branch_range() {
+        let far = |branch: &str, words: usize| {
+            format!("{} end\n{}end: exit", branch, "nop\n".repeat(words))
+        };
+
+        // 2^23 bytes is the first offset an if can't reach, 2^27 for goto and call
+        assert!(assemble(&far("ifeq", (1 << 21) - 2)).is_ok());
+        assert_eq!(
+            AsmError::new(1, "8388608 is out of range (-8388608 to 8388607)"),
+            assemble(&far("ifeq", (1 << 21) - 1)).unwrap_err()
+        );
+        assert!(assemble(&far("ifez", (1 << 21) - 1)).is_err());
+        assert!(assemble(&far("goto", (1 << 21) - 1)).is_ok());
+    }
+
+    #[test]
+    fn test_comment_inside_string() {
+        let program = assemble("stpush \"#\" # comment").unwrap();
+        let nop = Instruction::Nop().encode();
+        assert_eq!(&[MAGIC, 0xF001_0123, nop, nop, nop], &program[..]);
+    }
+
+    #[test]
+    fn test_header() {
+        let program = assemble(".entry main\nexit\nmain: exit 1\n.data\ntable: .word 7").unwrap();
+        let image = loader::load_words(&program, usize::MAX).unwrap();
+        let nop = Instruction::Nop().encode();
+
+        assert_eq!(loader::VERSION, image.version);
+        assert_eq!(1, image.entry);
+        assert_eq!(
+            vec![
+                Section {
+                    kind: SectionKind::Code,
+                    addr: 0,
+                    words: vec![0x0000_0000, 0x0000_0001, nop, nop],
+                },
+                Section {
+                    kind: SectionKind::Data,
+                    addr: 4,
+                    words: vec![7],
+                },
+            ],
+            image.sections
+        );
+
+        assert!(assemble(".entry nowhere").is_err());
+        assert!(assemble(".entry data\nexit\n.data\ndata: .word 1").is_err());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let (program, symbols) =
+            assemble_with_symbols("main:\n    stpush \"abcd\"\nend: exit\n.data\ntable: .word 1")
+                .unwrap();
+        assert_eq!(
+            Ok(program),
+            assemble("main:\n    stpush \"abcd\"\nend: exit\n.data\ntable: .word 1")
+        );
+        assert_eq!(Some(0), symbols.address("main"));
+        assert_eq!(Some(2), symbols.address("end"));
+        assert_eq!(Some(4), symbols.address("table"));
+        assert_eq!(
+            vec![(0, 2), (2, 3), (4, 5)],
+            symbols.lines.into_iter().collect::<Vec<_>>()
+        );
+    }
+}
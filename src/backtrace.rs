@@ -0,0 +1,160 @@
+// Backtraces: the calls that led to the instruction being executed
+//
+// `call` keeps its return address on the same stack as the data, where nothing
+// stops the program from overwriting it, so the stack alone can't say how the
+// machine got somewhere. The machine keeps a shadow call stack of its own with
+// every call that hasn't returned yet, see `Machine::call_stack`.
+
+use std::fmt;
+
+/// Frames shown from each end of a longer backtrace, runaway recursion would
+/// otherwise print thousands
+const SHOWN_FRAMES: usize = 10;
+
+/// A `call` that hasn't returned yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallSite {
+    /// Word index of the `call`
+    pub pc: i32,
+    /// Word index the matching `return` has to go back to
+    pub return_to: i32,
+}
+
+/// Where the machine is and the calls that got it there, innermost first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Backtrace {
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Word index of the current instruction in the innermost frame, of the
+    /// `call` in the others
+    pub pc: i32,
+    /// `pc` in terms of the program's symbols, e.g. `main+4 (sum.asm:3)`
+    pub location: Option<String>,
+}
+
+// One frame per line, `#1 0x0004 in main+4 (sum.asm:3)`
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hidden = self.frames.len().saturating_sub(2 * SHOWN_FRAMES);
+        let mut lines = Vec::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            if hidden > 0 && i == SHOWN_FRAMES {
+                lines.push(format!("... {} frames not shown", hidden));
+            }
+            if hidden > 0 && (SHOWN_FRAMES..SHOWN_FRAMES + hidden).contains(&i) {
+                continue;
+            }
+            let mut line = format!("#{} 0x{:04x}", i, frame.pc as i64 * 4);
+            if let Some(location) = &frame.location {
+                line += &format!(" in {}", location);
+            }
+            lines.push(line);
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::{asm, Machine, MachineBuilder, RunOutcome};
+    use std::io;
+
+    // `sum` underflows the stack after dropping both the argument and its return
+    // address
+    const PROGRAM: &str = "\
+main:
+    push 1
+    call outer
+    exit
+outer:
+    call sum
+    return 4
+sum:
+    pop 8
+    add
+";
+
+    fn loaded(source: &str) -> Machine<io::Cursor<Vec<u8>>, Vec<u8>, Vec<u8>> {
+        let (program, mut symbols) = asm::assemble_with_symbols(source).unwrap();
+        symbols.file = Some("sum.asm".to_string());
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(Vec::new())
+            .diagnostics(Vec::new())
+            .build();
+        machine.set_symbols(symbols);
+        machine.load(&program).unwrap();
+        machine
+    }
+
+    #[test]
+    fn test_fault() {
+        let mut machine = loaded(PROGRAM);
+        let err = machine.run().unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::StackUnderflow));
+        assert_eq!(
+            "#0 0x0018 in sum+4 (sum.asm:10)\n\
+             #1 0x000c in outer (sum.asm:6)\n\
+             #2 0x0004 in main+4 (sum.asm:3)",
+            err.context.unwrap().backtrace.to_string()
+        );
+        assert_eq!(2, machine.call_stack().len());
+
+        // Without symbols there are only addresses
+        let frame = Frame {
+            pc: 5,
+            location: None,
+        };
+        let backtrace = Backtrace {
+            frames: vec![frame],
+        };
+        assert_eq!("#0 0x0014", backtrace.to_string());
+
+        // Only the ends of a deep recursion are shown
+        let backtrace = Backtrace {
+            frames: vec![backtrace.frames[0].clone(); 25],
+        };
+        let text = backtrace.to_string();
+        assert_eq!(21, text.lines().count());
+        assert!(
+            text.contains("#9 0x0014\n... 5 frames not shown\n#15 0x0014\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn test_corrupted_return() {
+        // `sum` overwrites its return address
+        let mut machine = loaded(
+            "main:\n    push 1\n    call sum\n    exit\nsum:\n    push 7\n    swap 0 4\n    pop 4\n    return 0\n",
+        );
+        let err = machine.run().unwrap_err();
+        assert!(
+            matches!(
+                err.kind,
+                ErrorKind::CorruptedReturn {
+                    call: 1,
+                    expected: 2,
+                    found: 7
+                }
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            "corrupted return to 0x001c, the call at 0x0004 returns to 0x0008",
+            err.kind.to_string()
+        );
+
+        // A return out of the entry point has no call to check against
+        let mut machine = loaded("main:\n    push 3\n    return 0\n    exit 1\n    exit 3\n");
+        assert_eq!(RunOutcome::Exited(3), machine.run().unwrap());
+        assert!(machine.call_stack().is_empty());
+    }
+}
@@ -0,0 +1,28 @@
+// A writer that can still be read from while a machine owns it
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+/// Collects everything written to it. Clones share the same buffer, so one can
+/// be handed to a `Machine` while another reads what it wrote.
+#[derive(Debug, Clone, Default)]
+pub struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Captured {
+    /// Everything written since the last `take`
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+// Code and branch coverage: which instructions ran, and which way each
+// conditional branch went
+//
+// Coverage from several runs (one per test input, say) can be merged before it
+// is written. Reports are either lcov tracefiles, with lines taken from the
+// assembly source when its symbols are known and otherwise numbering the
+// instructions from 1, or an annotated listing in the style of gcov, where
+// `#####` marks code that never ran.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::instruction::Instruction;
+use crate::loader::{Image, SectionKind};
+use crate::symbols::Symbols;
+
+/// Counts gathered while a machine runs, see `MachineBuilder::coverage`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    /// Executions of each word index
+    pcs: Vec<u64>,
+    /// Outcomes of the conditional branches that ran, by word index
+    branches: BTreeMap<usize, Branch>,
+}
+
+/// How often a conditional branch went each way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Totals over the code of an image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub instructions: usize,
+    pub executed: usize,
+    pub conditionals: usize,
+    /// Conditionals that were both taken and not taken
+    pub both_ways: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} instructions executed, {}/{} conditionals went both ways",
+            self.executed, self.instructions, self.both_ways, self.conditionals
+        )
+    }
+}
+
+impl Coverage {
+    pub fn new(memory_words: usize) -> Self {
+        Coverage {
+            pcs: vec![0; memory_words],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// Records `instruction` at `pc` having executed, moving the pc itself if
+    /// `jumped`
+    pub fn record(&mut self, pc: usize, instruction: Instruction, jumped: bool) {
+        self.pcs[pc] += 1;
+        if instruction.is_conditional() {
+            let branch = self.branches.entry(pc).or_default();
+            match jumped {
+                true => branch.taken += 1,
+                false => branch.not_taken += 1,
+            }
+        }
+    }
+
+    /// Adds the counts of another run of the same program
+    pub fn merge(&mut self, other: &Coverage) {
+        if self.pcs.len() < other.pcs.len() {
+            self.pcs.resize(other.pcs.len(), 0);
+        }
+        for (count, other) in self.pcs.iter_mut().zip(&other.pcs) {
+            *count += other;
+        }
+        for (&pc, other) in &other.branches {
+            let branch = self.branches.entry(pc).or_default();
+            branch.taken += other.taken;
+            branch.not_taken += other.not_taken;
+        }
+    }
+
+    /// Times the instruction at word index `pc` executed
+    pub fn count(&self, pc: usize) -> u64 {
+        self.pcs.get(pc).copied().unwrap_or(0)
+    }
+
+    /// Outcomes of the conditional at word index `pc`, if it ever ran
+    pub fn branch(&self, pc: usize) -> Option<Branch> {
+        self.branches.get(&pc).copied()
+    }
+
+    pub fn summary(&self, image: &Image) -> Summary {
+        let mut summary = Summary::default();
+        for (pc, instruction) in code(image) {
+            summary.instructions += 1;
+            if self.count(pc) > 0 {
+                summary.executed += 1;
+            }
+            if instruction.is_some_and(|instruction| instruction.is_conditional()) {
+                summary.conditionals += 1;
+                if self
+                    .branch(pc)
+                    .is_some_and(|branch| branch.taken > 0 && branch.not_taken > 0)
+                {
+                    summary.both_ways += 1;
+                }
+            }
+        }
+        summary
+    }
+
+    /// Writes an lcov tracefile for `source`, the file `image` was built from
+    pub fn write_lcov(
+        &self,
+        image: &Image,
+        symbols: &Symbols,
+        source: &str,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        // Instructions number their own lines when there's no source to go by
+        let line = |pc: usize| match symbols.lines.is_empty() {
+            true => Some(pc + 1),
+            false => symbols.line(pc),
+        };
+
+        // A line counts as often as its most executed instruction
+        let mut lines = BTreeMap::new();
+        let mut branches = Vec::new();
+        for (pc, instruction) in code(image) {
+            let Some(line) = line(pc) else {
+                continue;
+            };
+            let count = lines.entry(line).or_insert(0);
+            *count = self.count(pc).max(*count);
+            if instruction.is_some_and(|instruction| instruction.is_conditional()) {
+                branches.push((line, pc));
+            }
+        }
+
+        writeln!(out, "TN:")?;
+        writeln!(out, "SF:{}", source)?;
+        for (&line, &count) in &lines {
+            writeln!(out, "DA:{},{}", line, count)?;
+        }
+        writeln!(out, "LF:{}", lines.len())?;
+        writeln!(
+            out,
+            "LH:{}",
+            lines.values().filter(|&&count| count > 0).count()
+        )?;
+
+        let mut hit = 0;
+        for (block, &(line, pc)) in branches.iter().enumerate() {
+            let (taken, not_taken) = match self.branch(pc) {
+                Some(branch) => (branch.taken.to_string(), branch.not_taken.to_string()),
+                // lcov's way of saying the branch never ran
+                None => ("-".to_string(), "-".to_string()),
+            };
+            hit += [&taken, &not_taken]
+                .iter()
+                .filter(|count| !matches!(count.as_str(), "-" | "0"))
+                .count();
+            writeln!(out, "BRDA:{},{},0,{}", line, block, taken)?;
+            writeln!(out, "BRDA:{},{},1,{}", line, block, not_taken)?;
+        }
+        writeln!(out, "BRF:{}", branches.len() * 2)?;
+        writeln!(out, "BRH:{}", hit)?;
+        writeln!(out, "end_of_record")
+    }
+
+    /// Writes the program's code with how often each instruction ran, marking
+    /// conditionals that only ever went one way with `!`
+    pub fn write_listing(
+        &self,
+        image: &Image,
+        symbols: &Symbols,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        writeln!(out, "# {}", self.summary(image))?;
+
+        for (pc, instruction) in code(image) {
+            for (label, _) in symbols.labels.iter().filter(|(_, &addr)| addr == pc) {
+                writeln!(out, "{}:", label)?;
+            }
+
+            let count = match self.count(pc) {
+                0 => "#####".to_string(),
+                count => count.to_string(),
+            };
+            let text = match instruction {
+                Some(instruction) => instruction.to_string(),
+                None => format!(".word   0x{:08x}", image_word(image, pc)),
+            };
+            let branch = match (instruction, self.branch(pc)) {
+                (Some(instruction), _) if !instruction.is_conditional() => String::new(),
+                (_, None) => String::new(),
+                (_, Some(branch)) => {
+                    let one_way = branch.taken == 0 || branch.not_taken == 0;
+                    format!(
+                        "  # taken {}, not taken {}{}",
+                        branch.taken,
+                        branch.not_taken,
+                        if one_way { " !" } else { "" }
+                    )
+                }
+            };
+            let line = match symbols.line(pc) {
+                Some(line) => format!("{:>5}", line),
+                None => String::new(),
+            };
+            let text = match branch.is_empty() {
+                true => text,
+                false => format!("{:<20}{}", text, branch),
+            };
+            writeln!(out, "{:>10} {} {:04x}:  {}", count, line, pc * 4, text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Word index and decoded instruction of every code word of an image, leaving
+/// out the `nop`s the assembler pads the end of the code with
+fn code(image: &Image) -> impl Iterator<Item = (usize, Option<Instruction>)> + '_ {
+    image
+        .sections
+        .iter()
+        .filter(|section| section.kind == SectionKind::Code)
+        .flat_map(|section| {
+            let nop = Instruction::Nop().encode();
+            let padding = section.words.iter().rev().take_while(|&&word| word == nop);
+            let len = section.words.len() - padding.count();
+            section.words[..len]
+                .iter()
+                .enumerate()
+                .map(|(i, &word)| (section.addr + i, Instruction::decode(word).ok()))
+        })
+}
+
+fn image_word(image: &Image, pc: usize) -> u32 {
+    image
+        .sections
+        .iter()
+        .find(|section| (section.addr..section.end()).contains(&pc))
+        .map_or(0, |section| section.words[pc - section.addr])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader;
+    use crate::{asm, MachineBuilder};
+
+    // Prints the sign of its input, zero is never tested
+    const PROGRAM: &str = "\
+main:
+    input
+    ifmi negative
+    push 1
+    print
+    exit
+negative:
+    push -1
+    print
+    exit
+";
+
+    fn run(inputs: &[&str]) -> (Coverage, Image, Symbols) {
+        let (program, symbols) = asm::assemble_with_symbols(PROGRAM).unwrap();
+        let image = loader::load_words(&program, usize::MAX).unwrap();
+
+        let mut total = Coverage::new(0);
+        for input in inputs {
+            let mut machine = MachineBuilder::new()
+                .input(io::Cursor::new(input.as_bytes().to_vec()))
+                .output(Vec::new())
+                .diagnostics(Vec::new())
+                .coverage()
+                .build();
+            machine.load_image(&image).unwrap();
+            machine.run().unwrap();
+            total.merge(machine.coverage().unwrap());
+        }
+        (total, image, symbols)
+    }
+
+    #[test]
+    fn test_counts() {
+        let (coverage, image, _) = run(&["5\n", "7\n"]);
+        assert_eq!(2, coverage.count(0));
+        assert_eq!(0, coverage.count(5));
+        assert_eq!(
+            Some(Branch {
+                taken: 0,
+                not_taken: 2
+            }),
+            coverage.branch(1)
+        );
+        assert_eq!(None, coverage.branch(2));
+        assert_eq!(
+            "5/8 instructions executed, 0/1 conditionals went both ways",
+            coverage.summary(&image).to_string()
+        );
+
+        let (coverage, image, _) = run(&["5\n", "-7\n"]);
+        assert_eq!(8, coverage.summary(&image).executed);
+        assert_eq!(1, coverage.summary(&image).both_ways);
+    }
+
+    #[test]
+    fn test_lcov() {
+        let (coverage, image, symbols) = run(&["5\n"]);
+        let mut out = Vec::new();
+        coverage
+            .write_lcov(&image, &symbols, "sign.asm", &mut out)
+            .unwrap();
+        assert_eq!(
+            "TN:\nSF:sign.asm\n\
+             DA:2,1\nDA:3,1\nDA:4,1\nDA:5,1\nDA:6,1\nDA:8,0\nDA:9,0\nDA:10,0\n\
+             LF:8\nLH:5\n\
+             BRDA:3,0,0,0\nBRDA:3,0,1,1\nBRF:2\nBRH:1\nend_of_record\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        // Without symbols the lines are instruction numbers
+        let mut out = Vec::new();
+        Coverage::new(0)
+            .write_lcov(&image, &Symbols::default(), "sign.v", &mut out)
+            .unwrap();
+        let lcov = String::from_utf8(out).unwrap();
+        assert!(lcov.contains("DA:1,0\n"), "{}", lcov);
+        assert!(lcov.contains("BRDA:2,0,0,-\nBRDA:2,0,1,-\n"), "{}", lcov);
+    }
+
+    #[test]
+    fn test_listing() {
+        let (coverage, image, symbols) = run(&["5\n"]);
+        let mut out = Vec::new();
+        coverage.write_listing(&image, &symbols, &mut out).unwrap();
+        assert_eq!(
+            "# 5/8 instructions executed, 0/1 conditionals went both ways\n\
+             main:\n\
+             \x20        1     2 0000:  input\n\
+             \x20        1     3 0004:  ifmi    +16           # taken 0, not taken 1 !\n\
+             \x20        1     4 0008:  push    1\n\
+             \x20        1     5 000c:  print   0\n\
+             \x20        1     6 0010:  exit    0\n\
+             negative:\n\
+             \x20    #####     8 0014:  push    -1\n\
+             \x20    #####     9 0018:  print   0\n\
+             \x20    #####    10 001c:  exit    0\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,804 @@
+// A Debug Adapter Protocol server, so editors can launch and debug programs
+//
+// Messages are JSON with a Content-Length header, read from one stream and
+// written to another (stdin and stdout for `dap`). The machine has a single
+// thread with a single stack frame, whose one scope is the stack. The program's
+// output becomes output events, and its input comes from a file named in the
+// launch request since stdin belongs to the protocol.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::capture::Captured;
+use crate::instruction::Instruction;
+use crate::json::Json;
+use crate::symbols::{self, Symbols};
+use crate::{Machine, MachineBuilder, PauseHandle, StepOutcome};
+use crate::{DEFAULT_MEMORY_WORDS, MAX_MEMORY_WORDS};
+
+const THREAD_ID: i64 = 1;
+
+/// `variablesReference` of the stack scope
+const STACK_REFERENCE: i64 = 1;
+
+/// Instructions executed between sending what the program printed, while it runs
+const OUTPUT_INTERVAL: u64 = 4096;
+
+/// A launched program
+struct Program {
+    machine: Machine<Box<dyn Read>, Captured, Captured>,
+    /// What the program wrote that hasn't been sent in output events yet
+    output: Captured,
+    diagnostics: Captured,
+    symbols: Symbols,
+    /// Path of the .asm source, `None` for a .v image without a sidecar
+    source: Option<String>,
+    /// Word indexes to stop at
+    breakpoints: BTreeSet<usize>,
+    stop_on_entry: bool,
+    started: bool,
+    exited: bool,
+}
+
+impl Program {
+    /// True if execution at word index `pc` is at the start of a statement, the
+    /// granularity of stepping. Without a source every instruction is one.
+    fn at_statement(&self, pc: i32) -> bool {
+        let lines = &self.symbols.lines;
+        lines.is_empty() || usize::try_from(pc).is_ok_and(|pc| lines.contains_key(&pc))
+    }
+}
+
+/// How far `continue` and the stepping requests run
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Continue,
+    StepIn,
+    Next,
+    StepOut,
+}
+
+/// The sending side of the protocol
+struct Client<O: Write> {
+    out: O,
+    seq: i64,
+}
+
+impl<O: Write> Client<O> {
+    fn send(&mut self, kind: &str, mut members: Vec<(&str, Json)>) -> io::Result<()> {
+        members.splice(0..0, [("seq", self.seq.into()), ("type", kind.into())]);
+        self.seq += 1;
+        write_message(&mut self.out, &Json::object(members))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send("event", vec![("event", event.into()), ("body", body)])
+    }
+
+    /// Sends whatever the program printed since the last call
+    fn output(&mut self, program: &Program) -> io::Result<()> {
+        for (category, captured) in [
+            ("stdout", &program.output),
+            ("stderr", &program.diagnostics),
+        ] {
+            let bytes = captured.take();
+            if !bytes.is_empty() {
+                let text = String::from_utf8_lossy(&bytes).into_owned();
+                let body = Json::object([("category", category.into()), ("output", text.into())]);
+                self.event("output", body)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Adapter<O: Write> {
+    client: Client<O>,
+    /// Shared with the thread reading requests, which pauses the program
+    pause: PauseHandle,
+    program: Option<Program>,
+    /// Lines from the last `setBreakpoints`, resolved again once a program is launched
+    breakpoint_lines: Vec<usize>,
+    configured: bool,
+}
+
+/// Serves one debugging session, until the client disconnects or `input` ends.
+///
+/// Requests are read on a separate thread so that a `pause` can stop the
+/// program while it runs.
+pub fn serve(input: impl Read + Send + 'static, output: impl Write) -> io::Result<()> {
+    let pause = PauseHandle::default();
+    let (sender, requests) = mpsc::channel();
+
+    let reader_pause = pause.clone();
+    thread::spawn(move || {
+        let mut input = io::BufReader::new(input);
+        loop {
+            let message = read_message(&mut input);
+            if let Ok(Some(request)) = &message {
+                if request.get("command").and_then(Json::as_str) == Some("pause") {
+                    reader_pause.pause();
+                }
+            }
+            let last = !matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || last {
+                break;
+            }
+        }
+    });
+
+    let mut adapter = Adapter {
+        client: Client {
+            out: output,
+            seq: 1,
+        },
+        pause,
+        program: None,
+        breakpoint_lines: Vec::new(),
+        configured: false,
+    };
+    while let Ok(message) = requests.recv() {
+        match message? {
+            Some(request) if adapter.handle(&request)? => (),
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+impl<O: Write> Adapter<O> {
+    /// Answers a request, returning false once the session is over
+    fn handle(&mut self, request: &Json) -> io::Result<bool> {
+        let command = request
+            .get("command")
+            .and_then(Json::as_str)
+            .unwrap_or_default();
+        let args = request.get("arguments").unwrap_or(&Json::Null);
+
+        let mode = match command {
+            "continue" => Some(Mode::Continue),
+            "stepIn" => Some(Mode::StepIn),
+            "next" => Some(Mode::Next),
+            "stepOut" => Some(Mode::StepOut),
+            _ => None,
+        };
+
+        let result = match command {
+            "initialize" => Ok(Json::object([(
+                "supportsConfigurationDoneRequest",
+                true.into(),
+            )])),
+            "launch" => self.launch(args).map(|_| Json::Null),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "setExceptionBreakpoints" => Ok(Json::object([("breakpoints", vec![].into())])),
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Json::Null)
+            }
+            "threads" => Ok(Json::object([(
+                "threads",
+                vec![Json::object([
+                    ("id", THREAD_ID.into()),
+                    ("name", "main".into()),
+                ])]
+                .into(),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(Json::object([(
+                "scopes",
+                vec![Json::object([
+                    ("name", "Stack".into()),
+                    ("variablesReference", STACK_REFERENCE.into()),
+                    ("expensive", false.into()),
+                ])]
+                .into(),
+            )])),
+            "variables" => self.variables(args),
+            _ if mode.is_some() => match &self.program {
+                Some(program) if program.exited => Err("the program has exited".to_string()),
+                Some(program) if program.started => {
+                    Ok(Json::object([("allThreadsContinued", true.into())]))
+                }
+                _ => Err("the program isn't running".to_string()),
+            },
+            // A pause that arrives while the program runs has already stopped it,
+            // one that arrives while it is stopped must not stop the next run
+            "pause" => {
+                self.pause.cancel();
+                Ok(Json::Null)
+            }
+            "disconnect" | "terminate" => Ok(Json::Null),
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+
+        let succeeded = result.is_ok();
+        let mut response = vec![
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("success", succeeded.into()),
+            ("command", command.into()),
+        ];
+        match result {
+            Ok(Json::Null) => (),
+            Ok(body) => response.push(("body", body)),
+            Err(message) => response.push(("message", message.into())),
+        }
+        self.client.send("response", response)?;
+
+        match command {
+            "initialize" => self.client.event("initialized", Json::Null)?,
+            "launch" | "configurationDone" => self.start()?,
+            "disconnect" | "terminate" => return Ok(false),
+            _ => (),
+        }
+        if let (Some(mode), true) = (mode, succeeded) {
+            self.resume(mode)?;
+        }
+        Ok(true)
+    }
+
+    // `program` (required), `stopOnEntry`, `input` (a file for the program to
+    // read) and `memory` (in words)
+    fn launch(&mut self, args: &Json) -> Result<(), String> {
+        let path = args
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("launch needs a 'program'")?;
+        let memory_words = match args.get("memory") {
+            None => DEFAULT_MEMORY_WORDS,
+            Some(words) => words
+                .as_i64()
+                .and_then(|words| usize::try_from(words).ok())
+                .filter(|words| (1..=MAX_MEMORY_WORDS).contains(words))
+                .ok_or("'memory' must be a number of words")?,
+        };
+        let input: Box<dyn Read> = match args.get("input").and_then(Json::as_str) {
+            Some(input) => Box::new(File::open(input).map_err(|e| format!("{}: {}", input, e))?),
+            None => Box::new(io::empty()),
+        };
+
+        let (output, diagnostics) = (Captured::default(), Captured::default());
+        let mut machine = MachineBuilder::new()
+            .memory_words(memory_words)
+            .input(input)
+            .output(output.clone())
+            .diagnostics(diagnostics.clone())
+            .pause_handle(self.pause.clone())
+            .build();
+        let (image, symbols) = symbols::load(path).map_err(|e| format!("{}: {}", path, e))?;
+        machine
+            .load_image(&image)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        machine.set_symbols(symbols.clone());
+
+        let (breakpoints, _) = resolve_lines(&symbols, &self.breakpoint_lines);
+        self.program = Some(Program {
+            machine,
+            output,
+            diagnostics,
+            source: symbols.file.clone().filter(|_| !symbols.lines.is_empty()),
+            symbols,
+            breakpoints,
+            stop_on_entry: args.get("stopOnEntry").and_then(Json::as_bool) == Some(true),
+            started: false,
+            exited: false,
+        });
+        Ok(())
+    }
+
+    // Before launch, the lines are checked against the source named in the request
+    fn set_breakpoints(&mut self, args: &Json) -> Json {
+        self.breakpoint_lines = args
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|breakpoint| breakpoint.get("line")?.as_i64())
+            .filter_map(|line| usize::try_from(line).ok())
+            .collect();
+
+        let source_symbols;
+        let symbols = match &self.program {
+            Some(program) => &program.symbols,
+            None => {
+                let path = args.get("source").and_then(|source| source.get("path"));
+                source_symbols = path
+                    .and_then(Json::as_str)
+                    .and_then(|path| symbols::load(path).ok())
+                    .map(|(_, symbols)| symbols)
+                    .unwrap_or_default();
+                &source_symbols
+            }
+        };
+
+        let (addresses, breakpoints) = resolve_lines(symbols, &self.breakpoint_lines);
+        if let Some(program) = &mut self.program {
+            program.breakpoints = addresses;
+        }
+        Json::object([("breakpoints", breakpoints.into())])
+    }
+
+    fn stack_trace(&self) -> Result<Json, String> {
+        let program = self.program.as_ref().ok_or("no program")?;
+        let machine = &program.machine;
+        let pc = machine.pc();
+
+        let location = usize::try_from(pc)
+            .ok()
+            .and_then(|pc| program.symbols.describe(pc))
+            .unwrap_or_else(|| format!("0x{:04x}", pc as i64 * 4));
+        let name = match machine.next_instruction() {
+            Ok(instruction) => format!("{}: {}", location, instruction),
+            Err(_) => location,
+        };
+        let line = usize::try_from(pc)
+            .ok()
+            .and_then(|pc| program.symbols.line(pc));
+
+        let mut frame = vec![
+            ("id", 0usize.into()),
+            ("name", name.into()),
+            ("line", line.unwrap_or(0).into()),
+            ("column", usize::from(line.is_some()).into()),
+            (
+                "instructionPointerReference",
+                format!("0x{:04x}", pc as i64 * 4).into(),
+            ),
+        ];
+        if let Some(path) = &program.source {
+            let name = Path::new(path)
+                .file_name()
+                .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+            frame.push((
+                "source",
+                Json::object([("name", name.into()), ("path", path.as_str().into())]),
+            ));
+        }
+
+        Ok(Json::object([
+            ("stackFrames", vec![Json::object(frame)].into()),
+            ("totalFrames", 1usize.into()),
+        ]))
+    }
+
+    // Every stack word, named by its `sp + <bytes>` offset
+    fn variables(&self, args: &Json) -> Result<Json, String> {
+        let program = self.program.as_ref().ok_or("no program")?;
+        if args.get("variablesReference").and_then(Json::as_i64) != Some(STACK_REFERENCE) {
+            return Ok(Json::object([("variables", vec![].into())]));
+        }
+
+        let stack = program.machine.stack();
+        let start = args
+            .get("start")
+            .and_then(Json::as_i64)
+            .and_then(|start| usize::try_from(start).ok())
+            .unwrap_or(0)
+            .min(stack.len());
+        let count = args
+            .get("count")
+            .and_then(Json::as_i64)
+            .and_then(|count| usize::try_from(count).ok())
+            .filter(|&count| count > 0)
+            .unwrap_or(stack.len());
+
+        let variables = stack
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(count)
+            .map(|(i, &word)| {
+                Json::object([
+                    ("name", format!("sp + {}", i * 4).into()),
+                    ("value", format!("{} (0x{:08x})", word as i32, word).into()),
+                    ("variablesReference", 0usize.into()),
+                ])
+            })
+            .collect::<Vec<_>>();
+        Ok(Json::object([("variables", variables.into())]))
+    }
+
+    /// Starts the program once it is both launched and configured
+    fn start(&mut self) -> io::Result<()> {
+        let Some(program) = &mut self.program else {
+            return Ok(());
+        };
+        if !self.configured || program.started {
+            return Ok(());
+        }
+
+        program.started = true;
+        if program.stop_on_entry {
+            return self.stopped("entry", None);
+        }
+        // `resume` only checks for breakpoints after each step
+        let entry = program.machine.pc();
+        if usize::try_from(entry).is_ok_and(|pc| program.breakpoints.contains(&pc)) {
+            return self.stopped("breakpoint", None);
+        }
+        self.resume(Mode::Continue)
+    }
+
+    fn resume(&mut self, mode: Mode) -> io::Result<()> {
+        let Some(program) = &mut self.program else {
+            return Ok(());
+        };
+        if program.exited {
+            return Ok(());
+        }
+
+        let machine = &mut program.machine;
+        let (pc, sp) = (machine.pc(), machine.sp());
+        let over_call =
+            mode == Mode::Next && matches!(machine.next_instruction(), Ok(Instruction::Call(_)));
+        let mut depth = 0;
+        let mut executed = 0u64;
+
+        let (reason, description) = loop {
+            let machine = &mut program.machine;
+            let instruction = machine.next_instruction().ok();
+            match machine.step() {
+                StepOutcome::Continued => (),
+                StepOutcome::Paused => break ("pause", None),
+                StepOutcome::NeedsInput => break ("pause", Some("waiting for input".to_string())),
+                StepOutcome::Exited(code) => {
+                    program.exited = true;
+                    self.client.output(program)?;
+                    self.client.event(
+                        "exited",
+                        Json::object([("exitCode", i64::from(code).into())]),
+                    )?;
+                    return self.client.event("terminated", Json::Null);
+                }
+                StepOutcome::Faulted(e) => {
+                    let description = e.to_string();
+                    writeln!(program.diagnostics, "{}", description)?;
+                    break ("exception", Some(description));
+                }
+                StepOutcome::Watchpoint(hits) => {
+                    let hits: Vec<String> = hits.iter().map(ToString::to_string).collect();
+                    break ("data breakpoint", Some(hits.join("\n")));
+                }
+            }
+            executed += 1;
+
+            let machine = &program.machine;
+            let done = match mode {
+                Mode::Continue => false,
+                Mode::Next if over_call => machine.pc() == pc + 1 && machine.sp() >= sp,
+                Mode::StepIn | Mode::Next => program.at_statement(machine.pc()),
+                // Out at a `return` that isn't matched by a `call` made along the way
+                Mode::StepOut => match instruction {
+                    Some(Instruction::Call(_)) => {
+                        depth += 1;
+                        false
+                    }
+                    Some(Instruction::Return(_)) if depth == 0 => true,
+                    Some(Instruction::Return(_)) => {
+                        depth -= 1;
+                        false
+                    }
+                    _ => false,
+                },
+            };
+            if done {
+                break ("step", None);
+            }
+            if usize::try_from(machine.pc()).is_ok_and(|pc| program.breakpoints.contains(&pc)) {
+                break ("breakpoint", None);
+            }
+            if executed.is_multiple_of(OUTPUT_INTERVAL) {
+                self.client.output(program)?;
+            }
+        };
+
+        self.stopped(reason, description)
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) -> io::Result<()> {
+        if let Some(program) = &self.program {
+            self.client.output(program)?;
+        }
+
+        let mut body = vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ];
+        if let Some(description) = description {
+            body.push(("description", description.into()));
+        }
+        self.client.event("stopped", Json::object(body))
+    }
+}
+
+/// Word indexes for breakpoints on source lines, and the `Breakpoint` for each
+/// line telling the client where it really ended up
+fn resolve_lines(symbols: &Symbols, lines: &[usize]) -> (BTreeSet<usize>, Vec<Json>) {
+    let mut addresses = BTreeSet::new();
+    let breakpoints = lines
+        .iter()
+        .map(|&line| match symbols.line_address(line) {
+            Some((addr, line)) => {
+                addresses.insert(addr);
+                Json::object([("verified", true.into()), ("line", line.into())])
+            }
+            None => Json::object([
+                ("verified", false.into()),
+                ("message", "no code on or after this line".into()),
+            ]),
+        })
+        .collect();
+    (addresses, breakpoints)
+}
+
+/// Reads one message, or `None` at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        match line.trim_end() {
+            "" if length.is_some() => break,
+            "" => (),
+            header => {
+                if let Some(value) = header.strip_prefix("Content-Length:") {
+                    let value = value.trim();
+                    length = Some(
+                        value
+                            .parse::<usize>()
+                            .map_err(|_| invalid(format!("bad Content-Length '{}'", value)))?,
+                    );
+                }
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|e| invalid(e.to_string()))?;
+    Json::parse(&body).map(Some).map_err(invalid)
+}
+
+fn write_message(out: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `double` replaces its argument with twice its value
+    const PROGRAM: &str = "\
+main:
+    push 5
+    call double
+    print
+    exit 0
+double:
+    dup 4
+    dup 0
+    add
+    swap 0 8
+    return 4
+";
+
+    // Runs a session with `requests` (command and arguments, with `$program`
+    // standing for the path of `source`) and returns every message sent back
+    fn session(name: &str, source: &str, requests: &[(&str, &str)]) -> Vec<Json> {
+        let path =
+            std::env::temp_dir().join(format!("cosc365-dap-{}-{}.asm", name, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let mut input = Vec::new();
+        for (seq, (command, args)) in requests.iter().enumerate() {
+            let args = args.replace("$program", &path);
+            let request = format!(
+                r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+                seq + 1,
+                command,
+                args
+            );
+            write!(
+                input,
+                "Content-Length: {}\r\n\r\n{}",
+                request.len(),
+                request
+            )
+            .unwrap();
+        }
+
+        let mut output = Vec::new();
+        serve(io::Cursor::new(input), &mut output).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut output = io::Cursor::new(output);
+        std::iter::from_fn(|| read_message(&mut output).unwrap()).collect()
+    }
+
+    // `response:<command>` or `event:<event>` for each message
+    fn kinds(messages: &[Json]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| {
+                let kind = message.get("type").and_then(Json::as_str).unwrap();
+                let name = message.get("command").or(message.get("event"));
+                format!("{}:{}", kind, name.and_then(Json::as_str).unwrap())
+            })
+            .collect()
+    }
+
+    fn find<'a>(messages: &'a [Json], kind: &str, nth: usize) -> &'a Json {
+        let i = kinds(messages)
+            .iter()
+            .enumerate()
+            .filter(|(_, k)| *k == kind)
+            .nth(nth)
+            .unwrap()
+            .0;
+        &messages[i]
+    }
+
+    fn body<'a>(message: &'a Json, path: &[&str]) -> &'a Json {
+        path.iter()
+            .fold(message.get("body").unwrap(), |value, key| {
+                value.get(key).unwrap()
+            })
+    }
+
+    #[test]
+    fn test_session() {
+        let messages = session(
+            "session",
+            PROGRAM,
+            &[
+                ("initialize", r#"{"adapterID":"cosc365"}"#),
+                ("launch", r#"{"program":"$program"}"#),
+                (
+                    "setBreakpoints",
+                    r#"{"source":{"path":"$program"},"breakpoints":[{"line":6},{"line":40}]}"#,
+                ),
+                ("configurationDone", "{}"),
+                ("stackTrace", r#"{"threadId":1}"#),
+                ("variables", r#"{"variablesReference":1}"#),
+                ("next", r#"{"threadId":1}"#),
+                ("stepOut", r#"{"threadId":1}"#),
+                ("stackTrace", r#"{"threadId":1}"#),
+                ("continue", r#"{"threadId":1}"#),
+                ("disconnect", "{}"),
+            ],
+        );
+
+        assert_eq!(
+            vec![
+                "response:initialize",
+                "event:initialized",
+                "response:launch",
+                "response:setBreakpoints",
+                "response:configurationDone",
+                "event:stopped",
+                "response:stackTrace",
+                "response:variables",
+                "response:next",
+                "event:stopped",
+                "response:stepOut",
+                "event:stopped",
+                "response:stackTrace",
+                "response:continue",
+                "event:output",
+                "event:exited",
+                "event:terminated",
+                "response:disconnect",
+            ],
+            kinds(&messages)
+        );
+
+        // Line 6 is the label, so the breakpoint moves to the first instruction
+        assert_eq!(
+            r#"[{"verified":true,"line":7},{"verified":false,"message":"no code on or after this line"}]"#,
+            body(&messages[3], &["breakpoints"]).to_string()
+        );
+        assert_eq!(Some("breakpoint"), body(&messages[5], &["reason"]).as_str());
+
+        let frame = &body(&messages[6], &["stackFrames"]).as_array().unwrap()[0];
+        assert_eq!(
+            Some("double: dup     4"),
+            frame.get("name").and_then(Json::as_str)
+        );
+        assert_eq!(Some(7), frame.get("line").and_then(Json::as_i64));
+        assert_eq!(
+            r#"[{"name":"sp + 0","value":"2 (0x00000002)","variablesReference":0},{"name":"sp + 4","value":"5 (0x00000005)","variablesReference":0}]"#,
+            body(&messages[7], &["variables"]).to_string()
+        );
+
+        let frame = &body(&messages[12], &["stackFrames"]).as_array().unwrap()[0];
+        assert_eq!(Some(4), frame.get("line").and_then(Json::as_i64));
+        assert_eq!(Some("10\n"), body(&messages[14], &["output"]).as_str());
+        assert_eq!(Some(0), body(&messages[15], &["exitCode"]).as_i64());
+        assert!(messages
+            .iter()
+            .all(|m| m.get("success").is_none_or(|s| s == &Json::Bool(true))));
+    }
+
+    #[test]
+    fn test_breakpoint_on_entry() {
+        let messages = session(
+            "entry",
+            "push 1\nexit 3\n",
+            &[
+                ("initialize", "{}"),
+                ("launch", r#"{"program":"$program"}"#),
+                (
+                    "setBreakpoints",
+                    r#"{"source":{"path":"$program"},"breakpoints":[{"line":1}]}"#,
+                ),
+                ("configurationDone", "{}"),
+                ("continue", r#"{"threadId":1}"#),
+                ("next", r#"{"threadId":1}"#),
+            ],
+        );
+
+        let stopped = find(&messages, "event:stopped", 0);
+        assert_eq!(Some("breakpoint"), body(stopped, &["reason"]).as_str());
+        let exited = find(&messages, "event:exited", 0);
+        assert_eq!(Some(3), body(exited, &["exitCode"]).as_i64());
+
+        // Nothing is left to step through once it exited
+        let next = find(&messages, "response:next", 0);
+        assert_eq!(Some(false), next.get("success").and_then(Json::as_bool));
+        assert_eq!(
+            Some("the program has exited"),
+            next.get("message").and_then(Json::as_str)
+        );
+    }
+
+    #[test]
+    fn test_stop_on_entry_and_fault() {
+        let messages = session(
+            "fault",
+            "push 1\nadd\nexit 3\n",
+            &[
+                ("initialize", "{}"),
+                ("configurationDone", "{}"),
+                ("launch", r#"{"program":"$program","stopOnEntry":true}"#),
+                ("stepIn", r#"{"threadId":1}"#),
+                ("continue", r#"{"threadId":1}"#),
+                ("launch", r#"{"program":"/nonexistent.v"}"#),
+                ("evaluate", r#"{"expression":"1"}"#),
+            ],
+        );
+
+        let stopped = |nth| body(find(&messages, "event:stopped", nth), &["reason"]).clone();
+        assert_eq!(Json::from("entry"), stopped(0));
+        assert_eq!(Json::from("step"), stopped(1));
+        assert_eq!(Json::from("exception"), stopped(2));
+
+        let fault = find(&messages, "event:stopped", 2);
+        let description = body(fault, &["description"]).as_str().unwrap();
+        assert!(
+            description.starts_with("stack underflow"),
+            "{}",
+            description
+        );
+        let output = find(&messages, "event:output", 0);
+        assert_eq!(Some("stderr"), body(output, &["category"]).as_str());
+
+        let launch = find(&messages, "response:launch", 1);
+        assert_eq!(Some(false), launch.get("success").and_then(Json::as_bool));
+        let evaluate = find(&messages, "response:evaluate", 0);
+        assert_eq!(Some(false), evaluate.get("success").and_then(Json::as_bool));
+    }
+}
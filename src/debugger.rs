@@ -0,0 +1,764 @@
+// Interactive debugger: a small gdb-like command language on top of `Machine::step`
+//
+// Addresses and stack offsets are in bytes like everywhere else in the tools, and
+// stack slots are written `sp + 4` like the comments in the marz examples.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use crate::error::VmError;
+use crate::instruction::Instruction;
+use crate::symbols::Symbols;
+use crate::watch::{Access, Location, WatchHit, Watchpoint};
+use crate::{Machine, StepOutcome};
+
+/// Stack words `stack` shows unless told otherwise
+const DEFAULT_STACK_WORDS: usize = 16;
+
+const HELP: &str = "\
+break <loc>             stop before the instruction at <loc>: a label, label+bytes or byte address
+delete [<loc>]          remove the breakpoint at <loc>, or every breakpoint
+watch <word>            stop after an instruction writes <word>: an address, label or sp + <off>
+rwatch <word>           stop after an instruction reads <word>
+awatch <word>           stop after an instruction reads or writes <word>
+unwatch [<word>]        remove the watchpoints on <word>, or every watchpoint
+info                    list breakpoints and watchpoints
+step [<n>]              execute <n> instructions (default 1), stepping into calls
+next                    execute one instruction, running a call to completion
+finish                  run until the current function returns
+continue                run until a breakpoint or the end of the program
+where                   show the next instruction
+backtrace               show the next instruction and the calls that led to it
+stack [<n>]             show the top <n> stack words (default 16)
+set sp + <off> <value>  change the stack word <off> bytes from the top
+quit                    leave the debugger
+An empty line repeats the previous command.
+";
+
+/// Why a command that runs the program stopped
+enum Stop {
+    /// It did what it was asked to
+    Done,
+    Breakpoint,
+    Exited(u8),
+    Faulted(VmError),
+    NeedsInput,
+    Paused,
+    Watchpoint(Vec<WatchHit>),
+}
+
+/// A failed command. Usage errors are shown to the user, I/O errors end the session.
+enum CommandError {
+    Usage(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(e: io::Error) -> Self {
+        CommandError::Io(e)
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(msg: String) -> Self {
+        CommandError::Usage(msg)
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(msg: &str) -> Self {
+        CommandError::Usage(msg.to_string())
+    }
+}
+
+/// Drives a loaded machine from debugger commands
+pub struct Debugger<R: io::Read, W: io::Write, E: io::Write> {
+    machine: Machine<R, W, E>,
+    symbols: Symbols,
+    /// Word indexes to stop at
+    breakpoints: BTreeSet<usize>,
+    /// Set once the program exits, after which it can't run any further
+    exited: bool,
+    /// Repeated when the user enters an empty line
+    last_command: String,
+}
+
+impl<R: io::Read, W: io::Write, E: io::Write> Debugger<R, W, E> {
+    /// Debugs a machine that already has its program loaded. `symbols` may be
+    /// empty, addresses are then shown without labels.
+    pub fn new(mut machine: Machine<R, W, E>, symbols: Symbols) -> Self {
+        machine.set_symbols(symbols.clone());
+        Debugger {
+            machine,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            exited: false,
+            last_command: String::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine<R, W, E> {
+        &self.machine
+    }
+
+    /// Prompts for and executes commands until `quit` or the end of the commands.
+    ///
+    /// Commands come from `read_line` (with the same contract as
+    /// `BufRead::read_line`) rather than a `BufRead`, so that they can share
+    /// stdin with the program's own input without holding its lock.
+    pub fn run(
+        &mut self,
+        mut read_line: impl FnMut(&mut String) -> io::Result<usize>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        self.show_location(out)?;
+
+        loop {
+            write!(out, "(debug) ")?;
+            out.flush()?;
+
+            let mut line = String::new();
+            if read_line(&mut line)? == 0 {
+                writeln!(out)?;
+                return Ok(());
+            }
+            if !self.execute(&line, out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes a single command, returning false for `quit`
+    pub fn execute(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => {
+                self.last_command = line.to_string();
+                line.to_string()
+            }
+        };
+        let (command, args) = match line.split_once(char::is_whitespace) {
+            Some((command, args)) => (command, args.trim()),
+            None => (line.as_str(), ""),
+        };
+
+        let result = match command {
+            "" => Ok(()),
+            "b" | "break" => self.add_breakpoint(args, out),
+            "d" | "delete" => self.delete_breakpoint(args, out),
+            "watch" => self.add_watchpoint(args, Access::Write, out),
+            "rwatch" => self.add_watchpoint(args, Access::Read, out),
+            "awatch" => self.add_watchpoint(args, Access::ReadWrite, out),
+            "unwatch" => self.delete_watchpoint(args, out),
+            "i" | "info" => self.list_breakpoints(out),
+            "s" | "step" => self.step(args, out),
+            "n" | "next" => self.next(out),
+            "f" | "finish" => self.finish(out),
+            "c" | "continue" => self.resume(out, |_, _| false),
+            "w" | "where" => self.show_location(out).map_err(CommandError::from),
+            "bt" | "backtrace" => {
+                writeln!(out, "{}", self.machine.backtrace()).map_err(CommandError::from)
+            }
+            "stack" => self.show_stack(args, out),
+            "set" => self.set_stack_word(args, out),
+            "h" | "help" => write!(out, "{}", HELP).map_err(CommandError::from),
+            "q" | "quit" => return Ok(false),
+            _ => Err(format!("unknown command '{}', try 'help'", command).into()),
+        };
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(CommandError::Usage(msg)) => writeln!(out, "{}", msg).map(|_| true),
+            Err(CommandError::Io(e)) => Err(e),
+        }
+    }
+
+    fn add_breakpoint(&mut self, args: &str, out: &mut impl Write) -> Result<(), CommandError> {
+        let addr = self.location(args)?;
+        self.breakpoints.insert(addr);
+        writeln!(out, "breakpoint at {}", self.describe(addr))?;
+        Ok(())
+    }
+
+    fn delete_breakpoint(&mut self, args: &str, out: &mut impl Write) -> Result<(), CommandError> {
+        if args.is_empty() {
+            self.breakpoints.clear();
+            writeln!(out, "deleted every breakpoint")?;
+            return Ok(());
+        }
+
+        let addr = self.location(args)?;
+        if !self.breakpoints.remove(&addr) {
+            return Err(format!("no breakpoint at {}", self.describe(addr)).into());
+        }
+        writeln!(out, "deleted breakpoint at {}", self.describe(addr))?;
+        Ok(())
+    }
+
+    fn list_breakpoints(&self, out: &mut impl Write) -> Result<(), CommandError> {
+        let watchpoints = self.machine.watchpoints();
+        if self.breakpoints.is_empty() && watchpoints.is_empty() {
+            writeln!(out, "no breakpoints")?;
+        }
+        for &addr in &self.breakpoints {
+            writeln!(out, "breakpoint at {}", self.describe(addr))?;
+        }
+        for watchpoint in watchpoints {
+            writeln!(
+                out,
+                "{} watchpoint on {}",
+                watchpoint.access,
+                word_address(watchpoint.index)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn add_watchpoint(
+        &mut self,
+        args: &str,
+        access: Access,
+        out: &mut impl Write,
+    ) -> Result<(), CommandError> {
+        let index = self.word(args)?;
+        self.machine.watch(Watchpoint { index, access });
+        writeln!(out, "{} watchpoint on {}", access, word_address(index))?;
+        Ok(())
+    }
+
+    fn delete_watchpoint(&mut self, args: &str, out: &mut impl Write) -> Result<(), CommandError> {
+        if args.is_empty() {
+            let watched: Vec<usize> = self.machine.watchpoints().iter().map(|w| w.index).collect();
+            for index in watched {
+                self.machine.unwatch(index);
+            }
+            writeln!(out, "deleted every watchpoint")?;
+            return Ok(());
+        }
+
+        let index = self.word(args)?;
+        if !self.machine.unwatch(index) {
+            return Err(format!("no watchpoint on {}", word_address(index)).into());
+        }
+        writeln!(out, "deleted watchpoints on {}", word_address(index))?;
+        Ok(())
+    }
+
+    fn step(&mut self, args: &str, out: &mut impl Write) -> Result<(), CommandError> {
+        let mut count = match args {
+            "" => 1,
+            _ => parse_number(args)
+                .filter(|&count| count > 0)
+                .ok_or("step expects a positive number of instructions")?,
+        };
+        self.resume(out, |_, _| {
+            count -= 1;
+            count == 0
+        })
+    }
+
+    // A call is run until execution is back after it with the return address
+    // popped, anything else is a single step
+    fn next(&mut self, out: &mut impl Write) -> Result<(), CommandError> {
+        let (pc, sp) = (self.machine.pc(), self.machine.sp());
+        match self.machine.next_instruction() {
+            Ok(Instruction::Call(_)) => self.resume(out, |machine, _| {
+                machine.pc() == pc + 1 && machine.sp() >= sp
+            }),
+            _ => self.resume(out, |_, _| true),
+        }
+    }
+
+    // Runs until a `return` that isn't matched by a `call` made along the way
+    fn finish(&mut self, out: &mut impl Write) -> Result<(), CommandError> {
+        let mut depth = 0;
+        self.resume(out, |_, instruction| match instruction {
+            Some(Instruction::Call(_)) => {
+                depth += 1;
+                false
+            }
+            Some(Instruction::Return(_)) if depth == 0 => true,
+            Some(Instruction::Return(_)) => {
+                depth -= 1;
+                false
+            }
+            _ => false,
+        })
+    }
+
+    /// Steps until `done`, given the machine and the instruction just executed,
+    /// returns true, a breakpoint is reached or the program stops. Always
+    /// executes at least one instruction, so it can leave a breakpoint.
+    fn resume(
+        &mut self,
+        out: &mut impl Write,
+        mut done: impl FnMut(&Machine<R, W, E>, Option<Instruction>) -> bool,
+    ) -> Result<(), CommandError> {
+        if self.exited {
+            return Err("the program has exited".into());
+        }
+
+        let stop = loop {
+            let instruction = self.machine.next_instruction().ok();
+            match self.machine.step() {
+                StepOutcome::Continued => (),
+                StepOutcome::Exited(code) => break Stop::Exited(code),
+                StepOutcome::NeedsInput => break Stop::NeedsInput,
+                StepOutcome::Faulted(e) => break Stop::Faulted(e),
+                StepOutcome::Paused => break Stop::Paused,
+                StepOutcome::Watchpoint(hits) => break Stop::Watchpoint(hits),
+            }
+
+            if done(&self.machine, instruction) {
+                break Stop::Done;
+            }
+            if self.breakpoints.contains(&(self.machine.pc() as usize)) {
+                break Stop::Breakpoint;
+            }
+        };
+
+        match stop {
+            Stop::Done => (),
+            Stop::Breakpoint => writeln!(out, "breakpoint")?,
+            Stop::Exited(code) => {
+                self.exited = true;
+                writeln!(out, "program exited with code {}", code)?;
+                return Ok(());
+            }
+            // The pc still points at the instruction, which can be retried
+            // after fixing the stack with `set`
+            Stop::Faulted(e) => writeln!(out, "program faulted: {}", e.kind)?,
+            Stop::NeedsInput => writeln!(out, "waiting for input")?,
+            Stop::Paused => writeln!(out, "paused")?,
+            Stop::Watchpoint(hits) => {
+                for hit in hits {
+                    writeln!(out, "watchpoint: {}", hit)?;
+                }
+            }
+        }
+        self.show_location(out)?;
+        Ok(())
+    }
+
+    fn show_location(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.exited {
+            return writeln!(out, "the program has exited");
+        }
+
+        let pc = self.machine.pc();
+        let location = match usize::try_from(pc) {
+            Ok(addr) => self.describe(addr),
+            Err(_) => format!("{}", pc as i64 * 4),
+        };
+        match self.machine.next_instruction() {
+            Ok(instruction) => match instruction.branch_offset() {
+                Some(offset) => {
+                    let target = pc as i64 + (offset >> 2) as i64;
+                    let target = match usize::try_from(target) {
+                        Ok(target) => self.describe(target),
+                        Err(_) => format!("{}", target * 4),
+                    };
+                    writeln!(out, "=> {}: {}  # {}", location, instruction, target)
+                }
+                None => writeln!(out, "=> {}: {}", location, instruction),
+            },
+            Err(e) => writeln!(out, "=> {}: {}", location, e),
+        }
+    }
+
+    fn show_stack(&self, args: &str, out: &mut impl Write) -> Result<(), CommandError> {
+        let count = match args {
+            "" => DEFAULT_STACK_WORDS,
+            _ => parse_number(args)
+                .and_then(|count| usize::try_from(count).ok())
+                .ok_or("stack expects a number of words")?,
+        };
+
+        let stack = self.machine.stack();
+        if stack.is_empty() {
+            writeln!(out, "the stack is empty")?;
+        }
+        for (i, &word) in stack.iter().take(count).enumerate() {
+            let slot = format!("sp + {}:", i * 4);
+            writeln!(out, "{:<10} 0x{:08x}  {}", slot, word, word as i32)?;
+        }
+        if stack.len() > count {
+            writeln!(out, "({} more words)", stack.len() - count)?;
+        }
+        Ok(())
+    }
+
+    // Accepts `sp + 4 = 42`, `sp+4 42` or just `4 42`
+    fn set_stack_word(&mut self, args: &str, out: &mut impl Write) -> Result<(), CommandError> {
+        let args = args.replace('=', " ");
+        let args = args.trim_start();
+        let args = args.strip_prefix("sp").unwrap_or(args).trim_start();
+        let args = args.strip_prefix('+').unwrap_or(args);
+
+        let mut parts = args.split_whitespace();
+        let (Some(offset), Some(value), None) = (parts.next(), parts.next(), parts.next()) else {
+            return Err("usage: set sp + <offset> <value>".into());
+        };
+        let offset = parse_number(offset)
+            .filter(|&offset| offset >= 0 && offset % 4 == 0)
+            .ok_or("the offset must be a number of bytes that is a multiple of 4")?;
+        let value = parse_number(value)
+            .filter(|value| (i32::MIN as i64..=u32::MAX as i64).contains(value))
+            .ok_or("the value must fit in 32 bits")?;
+
+        let index = self.machine.sp() as i64 + offset / 4;
+        let memory = self.machine.memory_mut();
+        if index >= memory.len() as i64 {
+            return Err(format!("sp + {} is below the bottom of the stack", offset).into());
+        } else if index < 0 {
+            return Err(format!("sp + {} is outside of memory", offset).into());
+        }
+        memory[index as usize] = value as u32;
+
+        writeln!(
+            out,
+            "sp + {} = 0x{:08x}  {}",
+            offset, value as u32, value as i32
+        )?;
+        Ok(())
+    }
+
+    /// Word index of a byte address, label or `label+bytes`
+    fn location(&self, text: &str) -> Result<usize, CommandError> {
+        let (bytes, what) = match parse_number(text) {
+            Some(bytes) => (bytes, "address"),
+            None => {
+                let (name, offset) = match text.split_once('+') {
+                    Some((name, offset)) => (name.trim(), parse_number(offset.trim())),
+                    None => (text, Some(0)),
+                };
+                if name.is_empty() {
+                    return Err("expected an address or label".into());
+                }
+                let addr = self
+                    .symbols
+                    .address(name)
+                    .ok_or_else(|| format!("no label named '{}'", name))?;
+                let offset = offset.ok_or_else(|| format!("bad offset in '{}'", text))?;
+                (addr as i64 * 4 + offset, "location")
+            }
+        };
+
+        if bytes < 0 || bytes % 4 != 0 || bytes / 4 >= self.machine.memory().len() as i64 {
+            return Err(format!("{} {} is not a word in memory", what, text).into());
+        }
+        Ok((bytes / 4) as usize)
+    }
+
+    /// Word index of a location or a stack slot, resolved against the current sp
+    fn word(&self, text: &str) -> Result<usize, CommandError> {
+        if !text.starts_with("sp") {
+            return self.location(text);
+        }
+        let location = Location::parse(text)?;
+        let memory_words = self.machine.memory().len();
+        Ok(location.index(self.machine.sp(), memory_words)?)
+    }
+
+    /// Byte address of word index `addr`, with its label if there is one
+    fn describe(&self, addr: usize) -> String {
+        let mut text = match self.symbols.describe(addr) {
+            Some(name) => format!("0x{:04x} <{}>", addr * 4, name),
+            None => format!("0x{:04x}", addr * 4),
+        };
+        if let Some(source) = self.symbols.source(addr) {
+            text += &format!(" ({})", source);
+        }
+        text
+    }
+}
+
+/// Byte address of a watched word. Labels are left out, since most watched
+/// words are on the stack rather than near one.
+fn word_address(index: usize) -> String {
+    format!("0x{:04x}", index * 4)
+}
+
+/// Parses a decimal or 0x-prefixed hex number, either possibly negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, MachineBuilder};
+
+    // `double` replaces its argument with twice its value
+    const PROGRAM: &str = "\
+main:
+    push 5
+    call double
+    print
+    exit
+double:
+    dup 4
+    dup 0
+    add
+    call noop
+    swap 0 8
+    return 4
+noop:
+    return 0
+";
+
+    type TestDebugger = Debugger<io::Cursor<Vec<u8>>, Vec<u8>, Vec<u8>>;
+
+    fn debugger(source: &str) -> TestDebugger {
+        let (program, symbols) = asm::assemble_with_symbols(source).unwrap();
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(Vec::new())
+            .diagnostics(Vec::new())
+            .build();
+        machine.load(&program).unwrap();
+        Debugger::new(machine, symbols)
+    }
+
+    // Executes a command and returns what it printed
+    fn execute(debugger: &mut TestDebugger, line: &str) -> String {
+        let mut out = Vec::new();
+        assert!(debugger.execute(line, &mut out).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = debugger(PROGRAM);
+
+        assert_eq!(
+            "breakpoint at 0x0010 <double>\n",
+            execute(&mut debugger, "break double")
+        );
+        assert_eq!(
+            "breakpoint at 0x0008 <main+8>\n",
+            execute(&mut debugger, "b 8")
+        );
+        assert_eq!(
+            "breakpoint at 0x0014 <double+4>\n",
+            execute(&mut debugger, "b double+4")
+        );
+        assert_eq!(
+            "breakpoint at 0x0008 <main+8>\n\
+             breakpoint at 0x0010 <double>\n\
+             breakpoint at 0x0014 <double+4>\n",
+            execute(&mut debugger, "info")
+        );
+        assert_eq!(
+            "no label named 'nowhere'\n",
+            execute(&mut debugger, "b nowhere")
+        );
+        assert_eq!(
+            "address 6 is not a word in memory\n",
+            execute(&mut debugger, "b 6")
+        );
+        assert_eq!(
+            "deleted breakpoint at 0x0014 <double+4>\n",
+            execute(&mut debugger, "delete double+4")
+        );
+
+        assert_eq!(
+            "breakpoint\n=> 0x0010 <double>: dup     4\n",
+            execute(&mut debugger, "continue")
+        );
+        assert_eq!(
+            "breakpoint\n=> 0x0008 <main+8>: print   0\n",
+            execute(&mut debugger, "c")
+        );
+        assert_eq!("program exited with code 0\n", execute(&mut debugger, "c"));
+        assert_eq!("the program has exited\n", execute(&mut debugger, "step"));
+    }
+
+    #[test]
+    fn test_step_next_finish() {
+        let mut debugger = debugger(PROGRAM);
+
+        assert_eq!(
+            "=> 0x0004 <main+4>: call    +12  # 0x0010 <double>\n",
+            execute(&mut debugger, "step")
+        );
+        // An empty line repeats the step
+        assert_eq!(
+            "=> 0x0010 <double>: dup     4\n",
+            execute(&mut debugger, "")
+        );
+        assert_eq!(
+            "=> 0x001c <double+12>: call    +12  # 0x0028 <noop>\n",
+            execute(&mut debugger, "step 3")
+        );
+        assert_eq!(
+            "#0 0x001c in double+12\n#1 0x0004 in main+4\n",
+            execute(&mut debugger, "bt")
+        );
+        assert_eq!(
+            "=> 0x0020 <double+16>: swap    0 8\n",
+            execute(&mut debugger, "next")
+        );
+        assert_eq!(
+            "=> 0x0020 <double+16>: swap    0 8\n",
+            execute(&mut debugger, "where")
+        );
+
+        let mut debugger = self::debugger(PROGRAM);
+        execute(&mut debugger, "step 2");
+        assert_eq!(
+            "=> 0x0008 <main+8>: print   0\n",
+            execute(&mut debugger, "finish")
+        );
+        assert_eq!(&[10], debugger.machine().stack());
+
+        // A breakpoint inside the call stops `next`
+        let mut debugger = self::debugger(PROGRAM);
+        execute(&mut debugger, "step");
+        execute(&mut debugger, "break noop");
+        assert_eq!(
+            "breakpoint\n=> 0x0028 <noop>: return  0\n",
+            execute(&mut debugger, "next")
+        );
+    }
+
+    #[test]
+    fn test_stack() {
+        let mut debugger = debugger(PROGRAM);
+        assert_eq!("the stack is empty\n", execute(&mut debugger, "stack"));
+
+        execute(&mut debugger, "step 4");
+        assert_eq!(
+            "sp + 0:    0x00000005  5\n\
+             sp + 4:    0x00000005  5\n\
+             sp + 8:    0x00000002  2\n\
+             sp + 12:   0x00000005  5\n",
+            execute(&mut debugger, "stack")
+        );
+        assert_eq!(
+            "sp + 0:    0x00000005  5\n(3 more words)\n",
+            execute(&mut debugger, "stack 1")
+        );
+
+        assert_eq!(
+            "sp + 8 = 0x00000007  7\n",
+            execute(&mut debugger, "set sp + 8 = 7")
+        );
+        assert_eq!(
+            "sp + 0 = 0xffffffff  -1\n",
+            execute(&mut debugger, "set sp+0 -1")
+        );
+        assert_eq!(&[u32::MAX, 5, 7, 5], debugger.machine().stack());
+        assert_eq!(
+            "sp + 16 is below the bottom of the stack\n",
+            execute(&mut debugger, "set 16 0")
+        );
+        assert_eq!(
+            "the offset must be a number of bytes that is a multiple of 4\n",
+            execute(&mut debugger, "set sp + 2 0")
+        );
+        assert_eq!(
+            "usage: set sp + <offset> <value>\n",
+            execute(&mut debugger, "set sp + 4")
+        );
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut debugger = debugger(PROGRAM);
+        execute(&mut debugger, "step");
+
+        assert_eq!(
+            "write watchpoint on 0x0ffc\n",
+            execute(&mut debugger, "watch sp + 0")
+        );
+        assert_eq!(
+            "read watchpoint on 0x0ffc\n",
+            execute(&mut debugger, "rwatch sp")
+        );
+        assert_eq!(
+            "sp + 4 is not a word in memory\n",
+            execute(&mut debugger, "awatch sp + 4")
+        );
+        assert_eq!(
+            "write watchpoint on 0x0ffc\nread watchpoint on 0x0ffc\n",
+            execute(&mut debugger, "info")
+        );
+
+        assert_eq!(
+            "watchpoint: read 0x0ffc (sp + 4): 0x00000005 at 0x0010: dup     4\n\
+             => 0x0014 <double+4>: dup     0\n",
+            execute(&mut debugger, "c")
+        );
+        // `swap` reads the word too, but is reported once as the write
+        assert_eq!(
+            "watchpoint: write 0x0ffc (sp + 8): 0x00000005 -> 0x0000000a \
+             at 0x0020: swap    0 8\n\
+             => 0x0024 <double+20>: return  4\n",
+            execute(&mut debugger, "c")
+        );
+
+        assert_eq!(
+            "deleted watchpoints on 0x0ffc\n",
+            execute(&mut debugger, "unwatch 0xffc")
+        );
+        assert_eq!(
+            "no watchpoint on 0x0ffc\n",
+            execute(&mut debugger, "unwatch sp + 8")
+        );
+        assert_eq!("program exited with code 0\n", execute(&mut debugger, "c"));
+    }
+
+    #[test]
+    fn test_fault() {
+        let mut debugger = debugger("push 1\nadd\nexit 3");
+
+        let out = execute(&mut debugger, "continue");
+        assert!(out.starts_with("program faulted: "), "{}", out);
+        assert!(out.ends_with("=> 0x0004: add\n"), "{}", out);
+
+        // The faulting instruction is retried rather than skipped
+        assert_eq!(out, execute(&mut debugger, "step"));
+        assert_eq!(1, debugger.machine().pc());
+    }
+
+    #[test]
+    fn test_run() {
+        let mut debugger = debugger(PROGRAM);
+        let mut commands = io::Cursor::new("b double\nc\nstack\nbogus\nquit\nc\n");
+        let mut out = Vec::new();
+
+        debugger
+            .run(|line| io::BufRead::read_line(&mut commands, line), &mut out)
+            .unwrap();
+        assert_eq!(
+            "=> 0x0000 <main>: push    5\n\
+             (debug) breakpoint at 0x0010 <double>\n\
+             (debug) breakpoint\n=> 0x0010 <double>: dup     4\n\
+             (debug) sp + 0:    0x00000002  2\nsp + 4:    0x00000005  5\n\
+             (debug) unknown command 'bogus', try 'help'\n\
+             (debug) ",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(4, debugger.machine().pc());
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(Some(12), parse_number("12"));
+        assert_eq!(Some(-0x1f), parse_number("-0x1F"));
+        assert_eq!(None, parse_number("sp"));
+        assert_eq!(None, parse_number(""));
+    }
+}
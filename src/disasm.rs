@@ -0,0 +1,319 @@
+// Disassembler that turns a program image back into marz assembly
+//
+// Every branch target in the code gets a synthesized label named after its byte
+// address, and runs of `push` words that encode a stack string are folded back
+// into `stpush`.
+// Each line carries a comment with the address and raw word(s) it came from.
+// Headered images also get their `.entry` and a `.data` block for read-only data.
+
+use std::collections::BTreeSet;
+
+use crate::escape::quote;
+use crate::instruction::Instruction;
+use crate::loader::{Image, Section, SectionKind};
+
+/// Disassembles a program image into source text that the assembler accepts
+pub fn disassemble(image: &Image) -> String {
+    let code: Vec<_> = image
+        .sections
+        .iter()
+        .filter(|s| s.kind == SectionKind::Code)
+        .collect();
+    let in_code = |target: i64| {
+        code.iter()
+            .any(|s| (s.addr as i64..(s.addr + s.words.len()) as i64).contains(&target))
+    };
+
+    // Only targets inside the code get a label, there's nowhere to define the rest
+    let mut labels = BTreeSet::new();
+    for section in &code {
+        for (i, &word) in section.words.iter().enumerate() {
+            if let Some(target) = Instruction::decode(word)
+                .ok()
+                .and_then(|instruction| branch_target(&instruction, section.addr + i))
+                .filter(|&target| in_code(target))
+            {
+                labels.insert(target);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    if image.version != 0 {
+        labels.insert(image.entry as i64);
+        out.push_str(&format!(
+            "# image version {}, flags 0x{:x}\n",
+            image.version, image.flags
+        ));
+        out.push_str(&format!(".entry  {}\n", label_name(image.entry as i64)));
+    }
+
+    for section in &image.sections {
+        match section.kind {
+            SectionKind::Code => disassemble_code(section, &labels, &mut out),
+            SectionKind::Data => {
+                out.push_str(&format!(".data   # {:04x}\n", section.addr * 4));
+                for (i, word) in section.words.iter().enumerate() {
+                    let text = format!(".word   0x{:08x}", word);
+                    out.push_str(&format!(
+                        "    {:<31} # {:04x}: {:08x}\n",
+                        text,
+                        (section.addr + i) * 4,
+                        word
+                    ));
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn disassemble_code(section: &Section, labels: &BTreeSet<i64>, out: &mut String) {
+    let code = &section.words[..];
+    let base = section.addr;
+
+    let mut i = 0;
+    while i < code.len() {
+        let addr = base + i;
+        if labels.contains(&(addr as i64)) {
+            out.push_str(&format!("{}:\n", label_name(addr as i64)));
+        }
+
+        let run = string_run(code, i, |j| labels.contains(&((base + j) as i64)));
+        let (text, len) = match run {
+            Some((s, len)) => (format!("stpush  {}", quote(s.as_bytes())), len),
+            None => match Instruction::decode(code[i]) {
+                Ok(instruction) => (render(&instruction, code[i], addr, labels), 1),
+                // Keep the word so the output still reassembles to the same image
+                Err(e) => (format!(".word   0x{:08x}  # {}", e.word(), e), 1),
+            },
+        };
+
+        let raw: Vec<String> = code[i..i + len]
+            .iter()
+            .map(|w| format!("{:08x}", w))
+            .collect();
+        out.push_str(&format!(
+            "    {:<31} # {:04x}: {}\n",
+            text,
+            addr * 4,
+            raw.join(" ")
+        ));
+
+        i += len;
+    }
+}
+
+/// Synthesized label for the instruction at word index `addr`
+fn label_name(addr: i64) -> String {
+    format!("L{:04x}", addr * 4)
+}
+
+/// Word index an instruction at `addr` may jump to, if it is a branch
+fn branch_target(instruction: &Instruction, addr: usize) -> Option<i64> {
+    let offset = instruction.branch_offset()?;
+    Some(addr as i64 + (offset >> 2) as i64)
+}
+
+/// Renders a single instruction at word index `addr` in assembler syntax, with
+/// branch targets replaced by their synthesized labels. A branch out of the code
+/// has no label to name, so it stays a `.word` with its offset in the comment,
+/// and so does any word the assembler wouldn't write back exactly.
+fn render(instruction: &Instruction, word: u32, addr: usize, labels: &BTreeSet<i64>) -> String {
+    if instruction.encode() != word || !whole_words(instruction) {
+        return format!(".word   0x{:08x}  # {}", word, instruction);
+    }
+
+    match branch_target(instruction, addr) {
+        Some(target) if labels.contains(&target) => {
+            format!("{:<8}{}", instruction.mnemonic(), label_name(target))
+        }
+        Some(_) => format!(
+            ".word   0x{:08x}  # {}, outside the code",
+            word, instruction
+        ),
+        None => instruction.to_string(),
+    }
+}
+
+/// Whether every byte offset in the instruction is a whole number of words, the
+/// only offsets the assembler accepts
+fn whole_words(instruction: &Instruction) -> bool {
+    let offset = match *instruction {
+        Instruction::Pop(offset) => offset as i32,
+        Instruction::Stprint(offset) | Instruction::Return(offset) | Instruction::Dup(offset) => {
+            offset
+        }
+        _ => instruction.branch_offset().unwrap_or(0),
+    };
+    offset % 4 == 0
+}
+
+/// Characters `stpush` can express (everything else would need an escape we don't have)
+fn is_string_char(byte: u8) -> bool {
+    byte == b'\n' || (0x20..0x7f).contains(&byte)
+}
+
+/// If the words starting at `addr` are the pushes `stpush` emits for some string,
+/// returns that string and how many words it spans.
+///
+/// The first word pushed holds the end of the string and has bit 24 clear, every
+/// following word has it set. A run never continues past a label, since a branch
+/// into the middle of a string can't be expressed in source.
+fn string_run(
+    code: &[u32],
+    addr: usize,
+    is_label: impl Fn(usize) -> bool,
+) -> Option<(String, usize)> {
+    let last_chunk = chunk_bytes(code[addr], 0)?;
+    // Only the final chunk may be padded, and only at its end
+    let text_len = last_chunk.iter().take_while(|&&b| b != 1).count();
+    if text_len == 0
+        || !last_chunk[..text_len].iter().all(|&b| is_string_char(b))
+        || !last_chunk[text_len..].iter().all(|&b| b == 1)
+    {
+        return None;
+    }
+
+    let mut chunks = vec![&last_chunk[..text_len]];
+    let mut storage = Vec::new();
+    let mut end = addr + 1;
+    while end < code.len() && !is_label(end) {
+        match chunk_bytes(code[end], 1) {
+            Some(chunk) if chunk.iter().all(|&b| is_string_char(b)) => storage.push(chunk),
+            _ => break,
+        }
+        end += 1;
+    }
+    chunks.extend(storage.iter().map(|c| &c[..]));
+
+    let bytes: Vec<u8> = chunks
+        .iter()
+        .rev()
+        .flat_map(|c| c.iter().copied())
+        .collect();
+    Some((String::from_utf8(bytes).ok()?, end - addr))
+}
+
+// The three characters of a string push whose continuation bit equals `continues`
+fn chunk_bytes(word: u32, continues: u32) -> Option<[u8; 3]> {
+    if word >> 24 != 0xF0 | continues {
+        return None;
+    }
+
+    Some([word as u8, (word >> 8) as u8, (word >> 16) as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::loader;
+
+    fn disassemble_words(program: &[u32]) -> String {
+        disassemble(&loader::load_words(program, usize::MAX).unwrap())
+    }
+
+    #[test]
+    fn test_round_trip_marz() {
+        let fixtures: [&[u8]; 5] = [
+            include_bytes!("../marz/all.v"),
+            include_bytes!("../marz/calc.v"),
+            include_bytes!("../marz/str.v"),
+            include_bytes!("../marz/swap.v"),
+            include_bytes!("../marz/strapped.v"),
+        ];
+
+        for fixture in fixtures {
+            let image = loader::load_bytes(fixture, usize::MAX).unwrap();
+            let source = disassemble(&image);
+            let program = asm::assemble(&source).unwrap();
+            assert_eq!(fixture, &asm::to_bytes(&program)[..], "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_stpush_folding() {
+        let program = asm::assemble(r#"stpush "Hello\\ \"World\"\n""#).unwrap();
+        let source = disassemble_words(&program);
+
+        assert!(source.contains(r#"stpush  "Hello\\ \"World\"\n""#));
+    }
+
+    #[test]
+    fn test_labels() {
+        let program = asm::assemble("goto end\nnop\nend:\nexit").unwrap();
+        let source = disassemble_words(&program);
+
+        assert!(source.contains("goto    L0008"));
+        assert!(source.contains("L0008:\n    exit"));
+    }
+
+    #[test]
+    fn test_illegal_word() {
+        let program = [crate::MAGIC, 0xA000_0000, 0x0000_0000];
+        let source = disassemble_words(&program);
+
+        assert!(source.contains(".word   0xa0000000"));
+        assert_eq!(program[..], asm::assemble(&source).unwrap()[..3]);
+    }
+
+    #[test]
+    fn test_branch_outside_code() {
+        let program = [
+            crate::MAGIC,
+            Instruction::Goto(-8).encode(),
+            Instruction::IfEq(64).encode(),
+            Instruction::Exit(0).encode(),
+        ];
+        let source = disassemble_words(&program);
+
+        assert!(
+            source.contains("# goto    -8, outside the code"),
+            "{}",
+            source
+        );
+        assert!(
+            source.contains("# ifeq    +64, outside the code"),
+            "{}",
+            source
+        );
+        assert!(!source.contains("goto    L"), "{}", source);
+        assert_eq!(program[..], asm::assemble(&source).unwrap()[..4]);
+    }
+
+    #[test]
+    fn test_unassemblable_word() {
+        // dup 3, goto +6, and an exit with bits set that are never read
+        let program = [crate::MAGIC, 0xC000_0003, 0x7000_0006, 0x0000_0F2C];
+        let source = disassemble_words(&program);
+
+        assert!(
+            source.contains(".word   0xc0000003  # dup     3"),
+            "{}",
+            source
+        );
+        assert!(
+            source.contains(".word   0x70000006  # goto    "),
+            "{}",
+            source
+        );
+        assert!(
+            source.contains(".word   0x00000f2c  # exit    44"),
+            "{}",
+            source
+        );
+        assert_eq!(program[..], asm::assemble(&source).unwrap()[..4]);
+    }
+
+    #[test]
+    fn test_header() {
+        let program = asm::assemble(".entry main\nexit\nmain: goto main\n.data\n.word 7").unwrap();
+        let source = disassemble_words(&program);
+
+        assert!(source.contains(".entry  L0004"));
+        assert!(source.contains(".data"));
+        assert_eq!(program, asm::assemble(&source).unwrap(), "{}", source);
+    }
+}
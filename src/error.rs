@@ -0,0 +1,193 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use crate::backtrace::Backtrace;
+use crate::instruction::{DecodeError, Instruction};
+use crate::loader::LoadError;
+
+/// An error raised while loading or running a program
+#[derive(Debug)]
+pub struct VmError {
+    pub kind: ErrorKind,
+    /// Where the machine was when the error happened, or `None` if the program
+    /// never started running (e.g. it failed to load)
+    pub context: Option<Context>,
+}
+
+/// Machine state at the start of the instruction that failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Context {
+    pub pc: i32,
+    pub sp: i32,
+    /// The failing instruction, `None` if the word at `pc` didn't decode
+    pub instruction: Option<Instruction>,
+    /// `pc` in terms of the program's symbols, e.g. `GetInputs+12 (calc.asm:62)`,
+    /// if the machine has any
+    pub location: Option<String>,
+    /// The calls that led to `pc`, empty if the machine didn't record them
+    pub backtrace: Backtrace,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// The image failed validation (bad magic, too large, ...)
+    Load(LoadError),
+    /// A push with no room left on the stack
+    StackOverflow,
+    /// An instruction needed more values than the stack holds
+    StackUnderflow,
+    /// A push would have overwritten the program, see `Protection`
+    StackCollision { index: i32 },
+    /// A write to the program with `Protection::ReadOnly`
+    WriteProtected { index: i32 },
+    /// The pc left the code with `Protection::ReadOnly`
+    NotExecutable { index: i32 },
+    /// A `return` went somewhere other than right after the `call` it returns
+    /// from, all as word indexes
+    CorruptedReturn {
+        call: i32,
+        expected: i32,
+        found: i32,
+    },
+    /// A memory access (or the pc) landed outside of ram
+    OutOfBounds { index: i32 },
+    /// The word at pc is not an instruction
+    IllegalInstruction(DecodeError),
+    /// Reading input or writing output failed
+    Io(io::Error),
+    /// `input` got a line that isn't a number
+    MalformedInput(String),
+    /// The program ran into one of the machine's limits before exiting
+    LimitExceeded { limit: Limit, executed: u64 },
+}
+
+/// A limit on how long a program may run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    /// Number of instructions
+    Fuel(u64),
+    /// Wall-clock time
+    Timeout(Duration),
+}
+
+impl VmError {
+    pub fn new(kind: ErrorKind, pc: i32, sp: i32, instruction: Option<Instruction>) -> Self {
+        VmError {
+            kind,
+            context: Some(Context {
+                pc,
+                sp,
+                instruction,
+                location: None,
+                backtrace: Backtrace::default(),
+            }),
+        }
+    }
+}
+
+impl From<ErrorKind> for VmError {
+    fn from(kind: ErrorKind) -> Self {
+        VmError {
+            kind,
+            context: None,
+        }
+    }
+}
+
+impl From<LoadError> for VmError {
+    fn from(e: LoadError) -> Self {
+        ErrorKind::Load(e).into()
+    }
+}
+
+impl From<io::Error> for ErrorKind {
+    fn from(e: io::Error) -> Self {
+        ErrorKind::Io(e)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Load(e) => write!(f, "{}", e),
+            ErrorKind::StackOverflow => write!(f, "stack overflow"),
+            ErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            ErrorKind::StackCollision { index } => write!(
+                f,
+                "stack ran into the program at 0x{:04x}",
+                *index as i64 * 4
+            ),
+            ErrorKind::WriteProtected { index } => {
+                write!(
+                    f,
+                    "write to the read-only program at 0x{:04x}",
+                    *index as i64 * 4
+                )
+            }
+            ErrorKind::NotExecutable { index } => {
+                write!(f, "pc 0x{:04x} is outside the code", *index as i64 * 4)
+            }
+            ErrorKind::CorruptedReturn {
+                call,
+                expected,
+                found,
+            } => write!(
+                f,
+                "corrupted return to 0x{:04x}, the call at 0x{:04x} returns to 0x{:04x}",
+                *found as i64 * 4,
+                *call as i64 * 4,
+                *expected as i64 * 4
+            ),
+            ErrorKind::OutOfBounds { index } => {
+                write!(f, "access to 0x{:04x} is out of bounds", *index as i64 * 4)
+            }
+            ErrorKind::IllegalInstruction(e) => write!(f, "illegal instruction: {}", e),
+            ErrorKind::Io(e) => write!(f, "I/O error: {}", e),
+            ErrorKind::MalformedInput(s) => write!(f, "malformed input '{}'", s),
+            ErrorKind::LimitExceeded { limit, executed } => {
+                match limit {
+                    Limit::Fuel(fuel) => write!(f, "instruction limit of {} exceeded", fuel)?,
+                    Limit::Timeout(timeout) => write!(f, "time limit of {:?} exceeded", timeout)?,
+                }
+                write!(f, " after {} instructions", executed)
+            }
+        }
+    }
+}
+
+// Addresses are shown in bytes, matching the disassembler and the marz comments
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+
+        if let Some(ctx) = &self.context {
+            if let Some(location) = &ctx.location {
+                write!(f, " in {}", location)?;
+            }
+            write!(
+                f,
+                " (pc 0x{:04x}, sp 0x{:04x}",
+                ctx.pc as i64 * 4,
+                ctx.sp as i64 * 4
+            )?;
+            if let Some(instruction) = &ctx.instruction {
+                write!(f, ", executing `{}`", instruction)?;
+            }
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for VmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Load(e) => Some(e),
+            ErrorKind::IllegalInstruction(e) => Some(e),
+            ErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// Quoting of raw bytes as JSON style strings, shared by traces and the disassembler
+
+use std::fmt::Write as _;
+
+/// Quotes bytes as a JSON string, the way traces and disassembled `stpush`
+/// strings show them. Bytes outside printable ASCII become \u00XX escapes, which
+/// read back as the same byte.
+pub fn quote(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            0x20..=0x7e => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\u{:04x}", b);
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Parses a string written by `quote` at the start of `text`. Returns the bytes
+/// and the rest of `text` after the closing quote.
+pub fn unquote(text: &str) -> Result<(Vec<u8>, &str), String> {
+    let body = text.strip_prefix('"').ok_or("expected a string")?;
+    let mut bytes = Vec::new();
+    let mut chars = body.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((bytes, &body[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => bytes.push(b'"'),
+                Some((_, '\\')) => bytes.push(b'\\'),
+                Some((_, 'n')) => bytes.push(b'\n'),
+                Some((j, 'u')) => {
+                    let hex = body.get(j + 1..j + 5).ok_or("truncated \\u escape")?;
+                    let code = hex
+                        .strip_prefix("00")
+                        .and_then(|h| u8::from_str_radix(h, 16).ok())
+                        .ok_or_else(|| format!("unsupported escape \\u{}", hex))?;
+                    bytes.push(code);
+                    chars.nth(3);
+                }
+                _ => return Err("unknown escape".to_string()),
+            },
+            _ if c.is_ascii() => bytes.push(c as u8),
+            _ => return Err(format!("unexpected character '{}'", c)),
+        }
+    }
+
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = b"say \"hi\"\\\n\x00\x01\xff";
+        let quoted = quote(bytes);
+
+        assert_eq!(r#""say \"hi\"\\\n\u0000\u0001\u00ff""#, quoted);
+        assert_eq!(Ok((bytes.to_vec(), " rest")), unquote(&(quoted + " rest")));
+        assert!(unquote("\"\\u0100\"").is_err());
+        assert!(unquote("\"open").is_err());
+    }
+}
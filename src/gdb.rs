@@ -0,0 +1,739 @@
+// A stub for the GDB remote serial protocol, so gdb can debug a program with
+// `target remote`
+//
+// gdb sees memory as bytes, with each word of `ram` stored little-endian like in a
+// .v file. The pc and sp registers are byte addresses, so `x/4x $sp` shows the top
+// four stack words and `break *0x20` stops before the word at index 8.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use crate::watch::{Access, WatchHit, Watchpoint};
+use crate::{Machine, StepOutcome};
+
+/// The registers, in the order `g` reports them. The description names no
+/// architecture since gdb knows nothing like this machine.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.cosc365.machine.core">
+    <reg name="pc" bitsize="32" type="code_ptr" regnum="0"/>
+    <reg name="sp" bitsize="32" type="data_ptr" regnum="1"/>
+  </feature>
+</target>
+"#;
+
+/// Largest packet we accept, advertised to gdb in `qSupported`
+const PACKET_SIZE: usize = 0x1000;
+
+/// Instructions `continue` executes between checks for an interrupt from gdb
+const INTERRUPT_CHECK_INTERVAL: u64 = 1024;
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const SIGTTIN: u8 = 21;
+const SIGSEGV: u8 = 11;
+
+/// The interrupt character gdb sends when the user hits ^C
+const INTERRUPT: u8 = 0x03;
+
+/// A stream gdb is connected over
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// How a session ended
+#[derive(Debug, PartialEq)]
+pub enum SessionEnd {
+    /// The program exited with the given code
+    Exited(u8),
+    /// gdb detached, the program should carry on without it
+    Detached,
+    /// gdb killed the program or went away
+    Killed,
+}
+
+/// Waits for gdb to connect to `address`, a TCP port on localhost or, on Unix,
+/// the path of a socket to create
+pub fn accept(address: &str) -> io::Result<Box<dyn Connection>> {
+    if let Ok(port) = address.parse::<u16>() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        return Ok(Box::new(stream));
+    }
+
+    #[cfg(unix)]
+    {
+        let listener = std::os::unix::net::UnixListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        // Nobody else can connect now, so don't leave the socket behind
+        std::fs::remove_file(address)?;
+        Ok(Box::new(stream))
+    }
+
+    #[cfg(not(unix))]
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("expected a port number, got '{}'", address),
+    ))
+}
+
+/// Packet framing on top of a connection
+struct Session<'a> {
+    stream: &'a mut dyn Connection,
+    /// Bytes read ahead while checking for an interrupt
+    pending: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Session<'_> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pending.is_empty() && !self.closed {
+            let mut buf = [0; 256];
+            let read = self.stream.read(&mut buf)?;
+            self.pending.extend(&buf[..read]);
+            self.closed = read == 0;
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// Reads the next packet, acknowledging it. An interrupt comes back as a
+    /// packet of its own. Returns `None` once gdb hangs up.
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            // Skip acks (we never resend, the connection is reliable) and noise
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(vec![INTERRUPT])),
+                Some(b'$') => (),
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => {
+                        sum = sum.wrapping_add(byte);
+                        data.push(byte);
+                    }
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in &mut checksum {
+                *digit = match self.read_byte()? {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+            }
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if expected != Some(sum) {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+            return Ok(Some(unescape(&data)));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = vec![b'$'];
+        for &byte in data.as_bytes() {
+            if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+                packet.extend([b'}', byte ^ 0x20]);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let sum = packet[1..]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        packet.extend(format!("#{:02x}", sum).bytes());
+
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+
+    /// Checks, without blocking, whether gdb has asked to stop the program
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if !self.closed {
+            self.stream.set_nonblocking(true)?;
+            let mut buf = [0; 256];
+            let result = self.stream.read(&mut buf);
+            self.stream.set_nonblocking(false)?;
+
+            match result {
+                Ok(read) => {
+                    self.pending.extend(&buf[..read]);
+                    self.closed = read == 0;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // A program that runs forever would otherwise never notice gdb is gone
+        match self.pending.iter().position(|&byte| byte == INTERRUPT) {
+            Some(i) => {
+                self.pending.remove(i);
+                Ok(true)
+            }
+            None => Ok(self.closed),
+        }
+    }
+}
+
+/// Why the program stopped
+enum Stop {
+    Signal(u8),
+    /// A watchpoint triggered, with the stop reply that tells gdb which
+    Watch(String),
+    Exited(u8),
+}
+
+/// Lets gdb drive `machine`, which must already have its program loaded, until
+/// the program exits or gdb detaches or kills it
+pub fn serve<R: io::Read, W: io::Write, E: io::Write>(
+    machine: &mut Machine<R, W, E>,
+    stream: &mut dyn Connection,
+) -> io::Result<SessionEnd> {
+    let mut session = Session {
+        stream,
+        pending: VecDeque::new(),
+        closed: false,
+    };
+    let mut breakpoints = BTreeSet::new();
+    let mut last_signal = SIGTRAP;
+
+    while let Some(packet) = session.read_packet()? {
+        let packet = String::from_utf8_lossy(&packet).into_owned();
+
+        let reply = match packet.as_str() {
+            "\x03" => format!("S{:02x}", SIGINT),
+            "?" => format!("S{:02x}", last_signal),
+            "qAttached" => "1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "g" => registers(machine).concat(),
+            "s" | "c" => match resume(machine, &mut session, &breakpoints, packet == "s")? {
+                Stop::Signal(signal) => {
+                    last_signal = signal;
+                    format!("S{:02x}", last_signal)
+                }
+                Stop::Watch(reply) => {
+                    last_signal = SIGTRAP;
+                    reply
+                }
+                Stop::Exited(code) => {
+                    session.send(&format!("W{:02x}", code))?;
+                    return Ok(SessionEnd::Exited(code));
+                }
+            },
+            "k" => return Ok(SessionEnd::Killed),
+            _ if packet.starts_with("vKill") => {
+                session.send("OK")?;
+                return Ok(SessionEnd::Killed);
+            }
+            _ if packet == "D" || packet.starts_with("D;") => {
+                session.send("OK")?;
+                return Ok(SessionEnd::Detached);
+            }
+            _ if packet.starts_with("qSupported") => {
+                format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE)
+            }
+            _ if packet.starts_with("qXfer:features:read:") => read_target_xml(&packet),
+            _ if packet.starts_with('H') => "OK".to_string(),
+            _ if packet.starts_with('p') => {
+                let register = usize::from_str_radix(&packet[1..], 16).ok();
+                match register.and_then(|i| registers(machine).get(i).cloned()) {
+                    Some(value) => value,
+                    None => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with('m') => read_memory(machine, &packet[1..]),
+            _ if packet.starts_with('M') => write_memory(machine, &packet[1..]),
+            _ if packet.starts_with("Z0,") || packet.starts_with("Z1,") => {
+                match breakpoint_address(machine, &packet[3..]) {
+                    Some(addr) => {
+                        breakpoints.insert(addr);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with("z0,") || packet.starts_with("z1,") => {
+                match breakpoint_address(machine, &packet[3..]) {
+                    Some(addr) => {
+                        breakpoints.remove(&addr);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            _ if ["Z2,", "Z3,", "Z4,", "z2,", "z3,", "z4,"]
+                .iter()
+                .any(|prefix| packet.starts_with(prefix)) =>
+            {
+                match watchpoint(machine, &packet) {
+                    Some(watchpoint) if packet.starts_with('Z') => {
+                        machine.watch(watchpoint);
+                        "OK".to_string()
+                    }
+                    Some(watchpoint) => {
+                        machine.unwatch(watchpoint.index);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            // An empty reply tells gdb the packet isn't supported
+            _ => String::new(),
+        };
+        session.send(&reply)?;
+    }
+
+    Ok(SessionEnd::Killed)
+}
+
+/// Executes one instruction, or until a breakpoint, interrupt or the program stops
+fn resume<R: io::Read, W: io::Write, E: io::Write>(
+    machine: &mut Machine<R, W, E>,
+    session: &mut Session,
+    breakpoints: &BTreeSet<usize>,
+    single: bool,
+) -> io::Result<Stop> {
+    let mut executed = 0u64;
+
+    loop {
+        match machine.step() {
+            StepOutcome::Continued => (),
+            StepOutcome::NeedsInput => {
+                // Nothing ran and nothing will until input shows up, so stop like a
+                // background process reading from its terminal
+                session.send(&format!("O{}", hex(b"waiting for input\n")))?;
+                return Ok(Stop::Signal(SIGTTIN));
+            }
+            StepOutcome::Exited(code) => return Ok(Stop::Exited(code)),
+            StepOutcome::Paused => return Ok(Stop::Signal(SIGINT)),
+            StepOutcome::Faulted(e) => {
+                // Console output, so the user sees why
+                session.send(&format!("O{}", hex(format!("{}\n", e).as_bytes())))?;
+                return Ok(Stop::Signal(SIGSEGV));
+            }
+            StepOutcome::Watchpoint(hits) => {
+                return Ok(Stop::Watch(watch_reply(machine, &hits[0])))
+            }
+        }
+
+        executed += 1;
+        if single || breakpoints.contains(&(machine.pc() as usize)) {
+            return Ok(Stop::Signal(SIGTRAP));
+        }
+        if executed.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && session.interrupted()? {
+            return Ok(Stop::Signal(SIGINT));
+        }
+    }
+}
+
+/// pc and sp as hex in target byte order, byte addresses like everything gdb sees
+fn registers<R: io::Read, W: io::Write, E: io::Write>(machine: &Machine<R, W, E>) -> Vec<String> {
+    [machine.pc(), machine.sp()]
+        .iter()
+        .map(|&register| hex(&(register as u32).wrapping_mul(4).to_le_bytes()))
+        .collect()
+}
+
+// `qXfer:features:read:target.xml:<offset>,<length>`
+fn read_target_xml(packet: &str) -> String {
+    let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") else {
+        return "E00".to_string();
+    };
+    let Some((offset, length)) = parse_range(range) else {
+        return "E00".to_string();
+    };
+
+    let start = offset.min(TARGET_XML.len());
+    let end = offset.saturating_add(length).min(TARGET_XML.len());
+    let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+    format!("{}{}", marker, &TARGET_XML[start..end])
+}
+
+// `m<addr>,<length>`, replying with as many bytes as are in memory
+fn read_memory<R: io::Read, W: io::Write, E: io::Write>(
+    machine: &Machine<R, W, E>,
+    args: &str,
+) -> String {
+    let Some((addr, length)) = parse_range(args) else {
+        return "E01".to_string();
+    };
+    let memory = machine.memory();
+
+    let bytes: Vec<u8> = (addr..addr.saturating_add(length.min(PACKET_SIZE / 2)))
+        .map_while(|byte| {
+            memory
+                .get(byte / 4)
+                .map(|word| word.to_le_bytes()[byte % 4])
+        })
+        .collect();
+    if bytes.is_empty() && length > 0 {
+        return "E01".to_string();
+    }
+    hex(&bytes)
+}
+
+// `M<addr>,<length>:<bytes>`
+fn write_memory<R: io::Read, W: io::Write, E: io::Write>(
+    machine: &mut Machine<R, W, E>,
+    args: &str,
+) -> String {
+    let Some((range, data)) = args.split_once(':') else {
+        return "E01".to_string();
+    };
+    let (Some((addr, length)), Some(bytes)) = (parse_range(range), unhex(data)) else {
+        return "E01".to_string();
+    };
+    let memory = machine.memory_mut();
+    if bytes.len() != length || addr.saturating_add(length) > memory.len() * 4 {
+        return "E01".to_string();
+    }
+
+    for (byte, value) in (addr..).zip(bytes) {
+        let mut word = memory[byte / 4].to_le_bytes();
+        word[byte % 4] = value;
+        memory[byte / 4] = u32::from_le_bytes(word);
+    }
+    "OK".to_string()
+}
+
+/// Word index of a breakpoint's `<addr>,<kind>`, if it is a word in memory
+fn breakpoint_address<R: io::Read, W: io::Write, E: io::Write>(
+    machine: &Machine<R, W, E>,
+    args: &str,
+) -> Option<usize> {
+    let (addr, _kind) = parse_range(args)?;
+    (addr % 4 == 0 && addr / 4 < machine.memory().len()).then_some(addr / 4)
+}
+
+/// The watchpoint a `Z2`/`Z3`/`Z4` (write, read, access) packet or its `z`
+/// counterpart is about. gdb watches a single word at a time.
+fn watchpoint<R: io::Read, W: io::Write, E: io::Write>(
+    machine: &Machine<R, W, E>,
+    packet: &str,
+) -> Option<Watchpoint> {
+    let access = match &packet[1..] {
+        args if args.starts_with("2,") => Access::Write,
+        args if args.starts_with("3,") => Access::Read,
+        args if args.starts_with("4,") => Access::ReadWrite,
+        _ => return None,
+    };
+    let (addr, length) = parse_range(&packet[3..])?;
+    let index = addr / 4;
+    (addr % 4 == 0 && length <= 4 && index < machine.memory().len())
+        .then_some(Watchpoint { index, access })
+}
+
+/// The stop reply for a watchpoint hit, named after the kind of watchpoint set on
+/// the word so gdb can match it up
+fn watch_reply<R: io::Read, W: io::Write, E: io::Write>(
+    machine: &Machine<R, W, E>,
+    hit: &WatchHit,
+) -> String {
+    let exact = Watchpoint {
+        index: hit.index,
+        access: hit.access,
+    };
+    let kind = match hit.access {
+        _ if !machine.watchpoints().contains(&exact) => "awatch",
+        Access::Read => "rwatch",
+        _ => "watch",
+    };
+    format!("T{:02x}{}:{:x};", SIGTRAP, kind, hit.index * 4)
+}
+
+/// Parses the `<hex>,<hex>` most packets take
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (start, length) = text.split_once(',')?;
+    Some((
+        usize::from_str_radix(start, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Undoes the `}` escaping of `#`, `$`, `}` and `*` in packet data
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = data.iter();
+    let mut out = Vec::with_capacity(data.len());
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => out.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, MachineBuilder};
+
+    /// A connection replaying what gdb sent and collecting the replies
+    struct Script {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Script {
+        fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        format!("${}#{:02x}", data, sum)
+    }
+
+    // Runs `source` under a session sending `packets`, and returns how it ended
+    // along with the replies (without acks)
+    fn session(source: &str, packets: &[&str]) -> (SessionEnd, Vec<String>) {
+        session_with_input(io::Cursor::new(Vec::new()), source, packets)
+    }
+
+    fn session_with_input<R: Read>(
+        input: R,
+        source: &str,
+        packets: &[&str],
+    ) -> (SessionEnd, Vec<String>) {
+        let mut machine = MachineBuilder::new()
+            .input(input)
+            .output(Vec::new())
+            .diagnostics(Vec::new())
+            .build();
+        machine.load(&asm::assemble(source).unwrap()).unwrap();
+
+        let input: String = packets.iter().map(|data| packet(data) + "+").collect();
+        let mut script = Script {
+            input: io::Cursor::new(input.into_bytes()),
+            output: Vec::new(),
+        };
+        let end = serve(&mut machine, &mut script).unwrap();
+
+        let output = String::from_utf8(script.output).unwrap();
+        let replies = output
+            .split('$')
+            .skip(1)
+            .map(|reply| {
+                let (data, sum) = reply.split_once('#').unwrap();
+                assert_eq!(packet(data), format!("${}#{}", data, &sum[..2]));
+                data.to_string()
+            })
+            .collect();
+        (end, replies)
+    }
+
+    // Loops forever once it gets going
+    const PROGRAM: &str = "push 7\npush 8\nloop: add\ndup 0\ngoto loop\n";
+
+    #[test]
+    fn test_registers_and_memory() {
+        let (end, replies) = session(
+            PROGRAM,
+            &[
+                "qSupported:xmlRegisters=i386",
+                "?",
+                "s",
+                "g",
+                "p1",
+                "p2",
+                "m0,6",
+                "M1000,4:01020304",
+                "mffc,4",
+                "mfff,4",
+                "k",
+            ],
+        );
+        assert_eq!(SessionEnd::Killed, end);
+        assert_eq!(
+            vec![
+                "PacketSize=1000;qXfer:features:read+",
+                "S05",
+                "S05",
+                // pc = 4, sp = 0x0ffc
+                "04000000fc0f0000",
+                "fc0f0000",
+                "E01",
+                "070000f00800",
+                "E01",
+                "07000000",
+                "00",
+            ],
+            replies[..10]
+        );
+        assert_eq!(10, replies.len());
+
+        let (_, replies) = session(PROGRAM, &["M0,4:0a0000f0", "m0,4", "M0,2:00", "D"]);
+        assert_eq!(vec!["OK", "0a0000f0", "E01", "OK"], replies);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let (end, replies) = session(
+            PROGRAM,
+            &[
+                "Z0,c,4",
+                "c",
+                "g",
+                "z0,c,4",
+                "Z0,d,4",
+                "Z0,10000,4",
+                "c",
+                "c",
+            ],
+        );
+        assert_eq!(
+            vec![
+                "OK",
+                "S05",
+                "0c000000fc0f0000",
+                "OK",
+                "E01",
+                "E01",
+                "S02",
+                "S02"
+            ],
+            replies
+        );
+        // Running out of script looks like gdb hanging up, which interrupts
+        assert_eq!(SessionEnd::Killed, end);
+
+        let (end, replies) = session("push 1\nexit 3\n", &["Z0,4,4", "c", "c"]);
+        assert_eq!(SessionEnd::Exited(3), end);
+        assert_eq!(vec!["OK", "S05", "W03"], replies);
+
+        let (_, replies) = session("add\n", &["c", "?"]);
+        assert!(replies[0].starts_with('O'), "{:?}", replies);
+        assert_eq!(vec!["S0b", "S0b"], replies[1..]);
+    }
+
+    #[test]
+    fn test_needs_input() {
+        // Input that never arrives, like a non-blocking pipe nobody writes to
+        struct Starved;
+
+        impl Read for Starved {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let (_, replies) = session_with_input(Starved, "input\nexit 1\n", &["s", "c", "g"]);
+        let waiting = format!("O{}", hex(b"waiting for input\n"));
+        assert_eq!(
+            vec![
+                waiting.as_str(),
+                "S15",
+                waiting.as_str(),
+                "S15",
+                // Still at the `input`
+                "0000000000100000"
+            ],
+            replies
+        );
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let (_, replies) = session(
+            PROGRAM,
+            &[
+                "Z2,ff8,4", "c", "g", "c", "z2,ff8,4", "Z3,ffc,4", "c", "Z4,ffa,4", "Z5,ff8,4", "?",
+            ],
+        );
+        assert_eq!(
+            vec![
+                "OK",
+                // `push 8` writes the word below the first push
+                "T05watch:ff8;",
+                "08000000f80f0000",
+                // `add` only reads it, `dup 0` pushes over it again
+                "T05watch:ff8;",
+                "OK",
+                "OK",
+                "T05rwatch:ffc;",
+                "E01",
+                "",
+                "S05",
+            ],
+            replies
+        );
+    }
+
+    #[test]
+    fn test_target_description() {
+        let (_, replies) = session(
+            PROGRAM,
+            &[
+                "qXfer:features:read:target.xml:0,20",
+                "qXfer:features:read:target.xml:20,ffff",
+                "qXfer:features:read:other.xml:0,10",
+            ],
+        );
+        assert_eq!(format!("m{}", &TARGET_XML[..0x20]), replies[0]);
+        assert_eq!(format!("l{}", &TARGET_XML[0x20..]), replies[1]);
+        assert_eq!("E00", replies[2]);
+
+        assert_eq!(b"a}b#".to_vec(), unescape(b"a}]b}\x03"));
+    }
+}
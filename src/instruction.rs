@@ -0,0 +1,620 @@
+// Instruction codec shared by the machine, the assembler and the disassembler
+//
+// Every instruction is a single 32 bit word. The top nibble is the opcode; how
+// the remaining 28 bits are laid out depends on the opcode:
+//
+//   Miscellaneous      [opcode:4][func4:4][operand:24]
+//   BinaryArithmetic   [opcode:4][func4:4][unused:24]
+//   UnaryArithmetic    [opcode:4][func4:4][unused:24]
+//   BinaryIf           [opcode:4][func3:3][offset:25]
+//   UnaryIf            [opcode:4][unused:1][func2:2][offset:25]
+//   Print              [opcode:4][offset:26][fmt:2]
+//   everything else    [opcode:4][immediate:28]
+//
+// Swap splits its 24 bit operand into two 12 bit word offsets [from:12][to:12],
+// and exit reads the low byte of its operand as the status, like marz/machine.
+// If offsets are written sign extended to 25 bits, like marz/assemble does, but
+// only the low 24 bits are read back, like marz/machine does.
+
+use std::fmt;
+
+const OPCODE_SHIFT: u32 = 28;
+const FUNC4_SHIFT: u32 = 24;
+const IF_FUNC_SHIFT: u32 = 25;
+
+const IMM28_BITS: u32 = 28;
+const MISC_OPERAND_BITS: u32 = 24;
+const SWAP_FIELD_BITS: u32 = 12;
+const EXIT_STATUS_BITS: u32 = 8;
+const IF_OFFSET_BITS: u32 = 24;
+const PRINT_OFFSET_BITS: u32 = 26;
+const PRINT_FMT_BITS: u32 = 2;
+
+fn mask(bits: u32) -> u32 {
+    (1 << bits) - 1
+}
+
+fn field(word: u32, shift: u32, bits: u32) -> u32 {
+    (word >> shift) & mask(bits)
+}
+
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The opcode nibble is not assigned to any instruction (10 and 11)
+    UnknownOpcode { word: u32, opcode: u8 },
+    /// The opcode is valid, but the function field under it is not
+    UnknownFunction { word: u32, opcode: u8, func: u8 },
+}
+
+impl DecodeError {
+    /// The raw word that failed to decode
+    pub fn word(&self) -> u32 {
+        match *self {
+            DecodeError::UnknownOpcode { word, .. } => word,
+            DecodeError::UnknownFunction { word, .. } => word,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DecodeError::UnknownOpcode { word, opcode } => {
+                write!(f, "unknown opcode {} in word 0x{:08x}", opcode, word)
+            }
+            DecodeError::UnknownFunction { word, opcode, func } => write!(
+                f,
+                "unknown function 0b{:b} for opcode {} in word 0x{:08x}",
+                func, opcode, word
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Opcode {
+    Miscellaneous = 0b0000,
+    Pop = 0b0001,
+    BinaryArithmetic = 0b0010,
+    UnaryArithmetic = 0b0011,
+    StringPrint = 0b0100,
+    Call = 0b0101,
+    Return = 0b0110,
+    Goto = 0b0111,
+    BinaryIf = 0b1000,
+    UnaryIf = 0b1001,
+    Dup = 0b1100,
+    Print = 0b1101,
+    Dump = 0b1110,
+    Push = 0b1111,
+}
+
+impl Opcode {
+    fn from_integer(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Miscellaneous),
+            1 => Some(Self::Pop),
+            2 => Some(Self::BinaryArithmetic),
+            3 => Some(Self::UnaryArithmetic),
+            4 => Some(Self::StringPrint),
+            5 => Some(Self::Call),
+            6 => Some(Self::Return),
+            7 => Some(Self::Goto),
+            8 => Some(Self::BinaryIf),
+            9 => Some(Self::UnaryIf),
+            12 => Some(Self::Dup),
+            13 => Some(Self::Print),
+            14 => Some(Self::Dump),
+            15 => Some(Self::Push),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u32 {
+        (self as u32) << OPCODE_SHIFT
+    }
+}
+
+/// A decoded instruction. Offsets are kept in the units the machine uses them in:
+/// `Swap` and `Print` hold word offsets, everything else holds byte offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Exit(u8),
+    Swap(i16, i16),
+    Nop(),
+    Input(),
+    Stinput(u32),
+    Debug(u32),
+    Pop(u32),
+    Add(),
+    Sub(),
+    Mul(),
+    Div(),
+    Rem(),
+    And(),
+    Or(),
+    Xor(),
+    Lsl(),
+    Lsr(),
+    Asr(),
+    Neg(),
+    Not(),
+    Stprint(i32),
+    Call(i32),
+    Return(i32),
+    Goto(i32),
+    IfEq(i32),
+    IfNe(i32),
+    IfLt(i32),
+    IfGt(i32),
+    IfLe(i32),
+    IfGe(i32),
+    EqZero(i32),
+    NeZero(i32),
+    LtZero(i32),
+    GeZero(i32),
+    Dup(i32),
+    Print(i32, i8),
+    Dump(),
+    Push(u32),
+}
+
+impl Instruction {
+    /// Decodes a single instruction word
+    pub fn decode(word: u32) -> Result<Self, DecodeError> {
+        let opcode_bits = field(word, OPCODE_SHIFT, 4) as u8;
+        let opcode = Opcode::from_integer(opcode_bits).ok_or(DecodeError::UnknownOpcode {
+            word,
+            opcode: opcode_bits,
+        })?;
+
+        let func4 = field(word, FUNC4_SHIFT, 4) as u8;
+        let unknown_function = |func| DecodeError::UnknownFunction {
+            word,
+            opcode: opcode_bits,
+            func,
+        };
+
+        let operand = field(word, 0, MISC_OPERAND_BITS);
+        let imm28 = field(word, 0, IMM28_BITS);
+        let simm28 = sign_extend(imm28, IMM28_BITS);
+        let if_offset = sign_extend(field(word, 0, IF_OFFSET_BITS), IF_OFFSET_BITS);
+
+        let instruction = match opcode {
+            Opcode::Miscellaneous => match func4 {
+                0b0000 => Instruction::Exit(field(operand, 0, EXIT_STATUS_BITS) as u8),
+                0b0001 => {
+                    let from = field(operand, SWAP_FIELD_BITS, SWAP_FIELD_BITS);
+                    let to = field(operand, 0, SWAP_FIELD_BITS);
+                    Instruction::Swap(
+                        sign_extend(from, SWAP_FIELD_BITS) as i16,
+                        sign_extend(to, SWAP_FIELD_BITS) as i16,
+                    )
+                }
+                0b0010 => Instruction::Nop(),
+                0b0100 => Instruction::Input(),
+                0b0101 => Instruction::Stinput(operand),
+                0b1111 => Instruction::Debug(operand),
+                _ => return Err(unknown_function(func4)),
+            },
+            Opcode::Pop => Instruction::Pop(imm28),
+            Opcode::BinaryArithmetic => match func4 {
+                0b0000 => Instruction::Add(),
+                0b0001 => Instruction::Sub(),
+                0b0010 => Instruction::Mul(),
+                0b0011 => Instruction::Div(),
+                0b0100 => Instruction::Rem(),
+                0b0101 => Instruction::And(),
+                0b0110 => Instruction::Or(),
+                0b0111 => Instruction::Xor(),
+                0b1000 => Instruction::Lsl(),
+                0b1001 => Instruction::Lsr(),
+                0b1011 => Instruction::Asr(),
+                _ => return Err(unknown_function(func4)),
+            },
+            Opcode::UnaryArithmetic => match func4 {
+                0b0000 => Instruction::Neg(),
+                0b0001 => Instruction::Not(),
+                _ => return Err(unknown_function(func4)),
+            },
+            Opcode::StringPrint => Instruction::Stprint(simm28),
+            Opcode::Call => Instruction::Call(simm28),
+            Opcode::Return => Instruction::Return(simm28),
+            Opcode::Goto => Instruction::Goto(simm28),
+            Opcode::BinaryIf => {
+                let func3 = field(word, IF_FUNC_SHIFT, 3) as u8;
+                match func3 {
+                    0b000 => Instruction::IfEq(if_offset),
+                    0b001 => Instruction::IfNe(if_offset),
+                    0b010 => Instruction::IfLt(if_offset),
+                    0b011 => Instruction::IfGt(if_offset),
+                    0b100 => Instruction::IfLe(if_offset),
+                    0b101 => Instruction::IfGe(if_offset),
+                    _ => return Err(unknown_function(func3)),
+                }
+            }
+            Opcode::UnaryIf => match field(word, IF_FUNC_SHIFT, 2) {
+                0b00 => Instruction::EqZero(if_offset),
+                0b01 => Instruction::NeZero(if_offset),
+                0b10 => Instruction::LtZero(if_offset),
+                _ => Instruction::GeZero(if_offset),
+            },
+            Opcode::Dup => Instruction::Dup(simm28),
+            Opcode::Print => {
+                let fmt = field(word, 0, PRINT_FMT_BITS);
+                let offset = field(word, PRINT_FMT_BITS, PRINT_OFFSET_BITS);
+                Instruction::Print(sign_extend(offset, PRINT_OFFSET_BITS), fmt as i8)
+            }
+            Opcode::Dump => Instruction::Dump(),
+            Opcode::Push => Instruction::Push(simm28 as u32),
+        };
+
+        Ok(instruction)
+    }
+
+    /// Encodes the instruction into a single word. Operands wider than their field
+    /// are truncated, so `decode(encode(i)) == i` for every in-range instruction.
+    pub fn encode(&self) -> u32 {
+        let misc = |func4: u32, operand: u32| {
+            Opcode::Miscellaneous.bits()
+                | (func4 << FUNC4_SHIFT)
+                | (operand & mask(MISC_OPERAND_BITS))
+        };
+        let func4 = |opcode: Opcode, func4: u32| opcode.bits() | (func4 << FUNC4_SHIFT);
+        let imm28 = |opcode: Opcode, value: u32| opcode.bits() | (value & mask(IMM28_BITS));
+        let cond = |opcode: Opcode, func: u32, offset: i32| {
+            opcode.bits() | (func << IF_FUNC_SHIFT) | (offset as u32 & mask(IF_FUNC_SHIFT))
+        };
+
+        match *self {
+            Instruction::Exit(code) => misc(0b0000, code as u32),
+            Instruction::Swap(from, to) => {
+                let from = from as u32 & mask(SWAP_FIELD_BITS);
+                let to = to as u32 & mask(SWAP_FIELD_BITS);
+                misc(0b0001, (from << SWAP_FIELD_BITS) | to)
+            }
+            Instruction::Nop() => misc(0b0010, 0),
+            Instruction::Input() => misc(0b0100, 0),
+            Instruction::Stinput(max_chars) => misc(0b0101, max_chars),
+            Instruction::Debug(value) => misc(0b1111, value),
+            Instruction::Pop(offset) => imm28(Opcode::Pop, offset),
+            Instruction::Add() => func4(Opcode::BinaryArithmetic, 0b0000),
+            Instruction::Sub() => func4(Opcode::BinaryArithmetic, 0b0001),
+            Instruction::Mul() => func4(Opcode::BinaryArithmetic, 0b0010),
+            Instruction::Div() => func4(Opcode::BinaryArithmetic, 0b0011),
+            Instruction::Rem() => func4(Opcode::BinaryArithmetic, 0b0100),
+            Instruction::And() => func4(Opcode::BinaryArithmetic, 0b0101),
+            Instruction::Or() => func4(Opcode::BinaryArithmetic, 0b0110),
+            Instruction::Xor() => func4(Opcode::BinaryArithmetic, 0b0111),
+            Instruction::Lsl() => func4(Opcode::BinaryArithmetic, 0b1000),
+            Instruction::Lsr() => func4(Opcode::BinaryArithmetic, 0b1001),
+            Instruction::Asr() => func4(Opcode::BinaryArithmetic, 0b1011),
+            Instruction::Neg() => func4(Opcode::UnaryArithmetic, 0b0000),
+            Instruction::Not() => func4(Opcode::UnaryArithmetic, 0b0001),
+            Instruction::Stprint(offset) => imm28(Opcode::StringPrint, offset as u32),
+            Instruction::Call(offset) => imm28(Opcode::Call, offset as u32),
+            Instruction::Return(offset) => imm28(Opcode::Return, offset as u32),
+            Instruction::Goto(offset) => imm28(Opcode::Goto, offset as u32),
+            Instruction::IfEq(offset) => cond(Opcode::BinaryIf, 0b000, offset),
+            Instruction::IfNe(offset) => cond(Opcode::BinaryIf, 0b001, offset),
+            Instruction::IfLt(offset) => cond(Opcode::BinaryIf, 0b010, offset),
+            Instruction::IfGt(offset) => cond(Opcode::BinaryIf, 0b011, offset),
+            Instruction::IfLe(offset) => cond(Opcode::BinaryIf, 0b100, offset),
+            Instruction::IfGe(offset) => cond(Opcode::BinaryIf, 0b101, offset),
+            Instruction::EqZero(offset) => cond(Opcode::UnaryIf, 0b00, offset),
+            Instruction::NeZero(offset) => cond(Opcode::UnaryIf, 0b01, offset),
+            Instruction::LtZero(offset) => cond(Opcode::UnaryIf, 0b10, offset),
+            Instruction::GeZero(offset) => cond(Opcode::UnaryIf, 0b11, offset),
+            Instruction::Dup(offset) => imm28(Opcode::Dup, offset as u32),
+            Instruction::Print(offset, fmt) => {
+                let offset = offset as u32 & mask(PRINT_OFFSET_BITS);
+                let fmt = fmt as u32 & mask(PRINT_FMT_BITS);
+                Opcode::Print.bits() | (offset << PRINT_FMT_BITS) | fmt
+            }
+            Instruction::Dump() => Opcode::Dump.bits(),
+            Instruction::Push(value) => imm28(Opcode::Push, value),
+        }
+    }
+}
+
+impl Instruction {
+    /// The assembler mnemonic for this instruction
+    pub fn mnemonic(&self) -> &'static str {
+        match *self {
+            Instruction::Exit(_) => "exit",
+            Instruction::Swap(_, _) => "swap",
+            Instruction::Nop() => "nop",
+            Instruction::Input() => "input",
+            Instruction::Stinput(_) => "stinput",
+            Instruction::Debug(_) => "debug",
+            Instruction::Pop(_) => "pop",
+            Instruction::Add() => "add",
+            Instruction::Sub() => "sub",
+            Instruction::Mul() => "mul",
+            Instruction::Div() => "div",
+            Instruction::Rem() => "rem",
+            Instruction::And() => "and",
+            Instruction::Or() => "or",
+            Instruction::Xor() => "xor",
+            Instruction::Lsl() => "lsl",
+            Instruction::Lsr() => "lsr",
+            Instruction::Asr() => "asr",
+            Instruction::Neg() => "neg",
+            Instruction::Not() => "not",
+            Instruction::Stprint(_) => "stprint",
+            Instruction::Call(_) => "call",
+            Instruction::Return(_) => "return",
+            Instruction::Goto(_) => "goto",
+            Instruction::IfEq(_) => "ifeq",
+            Instruction::IfNe(_) => "ifne",
+            Instruction::IfLt(_) => "iflt",
+            Instruction::IfGt(_) => "ifgt",
+            Instruction::IfLe(_) => "ifle",
+            Instruction::IfGe(_) => "ifge",
+            Instruction::EqZero(_) => "ifez",
+            Instruction::NeZero(_) => "ifnz",
+            Instruction::LtZero(_) => "ifmi",
+            Instruction::GeZero(_) => "ifpl",
+            Instruction::Dup(_) => "dup",
+            Instruction::Print(_, 1) => "printh",
+            Instruction::Print(_, 2) => "printb",
+            Instruction::Print(_, 3) => "printo",
+            Instruction::Print(_, _) => "print",
+            Instruction::Dump() => "dump",
+            Instruction::Push(_) => "push",
+        }
+    }
+
+    /// Whether this is a branch that may or may not be taken, the `if*` and
+    /// `*zero` instructions
+    pub fn is_conditional(&self) -> bool {
+        !matches!(self, Instruction::Call(_) | Instruction::Goto(_))
+            && self.branch_offset().is_some()
+    }
+
+    /// PC-relative byte offset of a branch, or `None` if this isn't one
+    pub fn branch_offset(&self) -> Option<i32> {
+        match *self {
+            Instruction::Call(offset)
+            | Instruction::Goto(offset)
+            | Instruction::IfEq(offset)
+            | Instruction::IfNe(offset)
+            | Instruction::IfLt(offset)
+            | Instruction::IfGt(offset)
+            | Instruction::IfLe(offset)
+            | Instruction::IfGe(offset)
+            | Instruction::EqZero(offset)
+            | Instruction::NeZero(offset)
+            | Instruction::LtZero(offset)
+            | Instruction::GeZero(offset) => Some(offset),
+            _ => None,
+        }
+    }
+}
+
+// Renders in assembler syntax, with operands in the byte units the assembler
+// takes. Branches have no label to show, so their target is the signed byte
+// offset from the branch itself.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = self.mnemonic();
+
+        if let Some(offset) = self.branch_offset() {
+            return write!(f, "{:<8}{:+}", mnemonic, offset);
+        }
+
+        match *self {
+            Instruction::Exit(code) => write!(f, "{:<8}{}", mnemonic, code),
+            Instruction::Swap(from, to) => {
+                write!(f, "{:<8}{} {}", mnemonic, from as i32 * 4, to as i32 * 4)
+            }
+            Instruction::Stinput(value) | Instruction::Debug(value) => {
+                write!(f, "{:<8}0x{:x}", mnemonic, value)
+            }
+            Instruction::Pop(offset) => write!(f, "{:<8}{}", mnemonic, offset as i32),
+            Instruction::Stprint(offset)
+            | Instruction::Return(offset)
+            | Instruction::Dup(offset) => {
+                write!(f, "{:<8}{}", mnemonic, offset)
+            }
+            Instruction::Print(offset, _) => write!(f, "{:<8}{}", mnemonic, offset * 4),
+            Instruction::Push(value) => write!(f, "{:<8}{}", mnemonic, value as i32),
+            _ => write!(f, "{}", mnemonic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every value of a `bits` wide field when it is small enough to enumerate,
+    // otherwise a stride across the range that still hits both ends and the
+    // sign boundary.
+    fn field_values(bits: u32) -> Vec<u32> {
+        if bits <= 16 {
+            return (0..=mask(bits)).collect();
+        }
+
+        let mut values: Vec<u32> = (0..=mask(bits)).step_by(4093).collect();
+        values.extend([mask(bits), 1 << (bits - 1), (1 << (bits - 1)) - 1]);
+        values
+    }
+
+    fn assert_round_trip(instruction: Instruction) {
+        let word = instruction.encode();
+        assert_eq!(Ok(instruction), Instruction::decode(word), "{:08x}", word);
+        assert_eq!(word, Instruction::decode(word).unwrap().encode());
+    }
+
+    #[test]
+    fn test_round_trip_operands() {
+        for code in 0..=mask(EXIT_STATUS_BITS) as u8 {
+            assert_round_trip(Instruction::Exit(code));
+        }
+
+        for from in field_values(SWAP_FIELD_BITS) {
+            for to in field_values(SWAP_FIELD_BITS) {
+                assert_round_trip(Instruction::Swap(
+                    sign_extend(from, SWAP_FIELD_BITS) as i16,
+                    sign_extend(to, SWAP_FIELD_BITS) as i16,
+                ));
+            }
+        }
+
+        for value in field_values(MISC_OPERAND_BITS) {
+            assert_round_trip(Instruction::Stinput(value));
+            assert_round_trip(Instruction::Debug(value));
+        }
+
+        for value in field_values(IMM28_BITS) {
+            let signed = sign_extend(value, IMM28_BITS);
+            assert_round_trip(Instruction::Pop(value));
+            assert_round_trip(Instruction::Stprint(signed));
+            assert_round_trip(Instruction::Call(signed));
+            assert_round_trip(Instruction::Return(signed));
+            assert_round_trip(Instruction::Goto(signed));
+            assert_round_trip(Instruction::Dup(signed));
+            assert_round_trip(Instruction::Push(signed as u32));
+        }
+
+        for value in field_values(IF_OFFSET_BITS) {
+            let offset = sign_extend(value, IF_OFFSET_BITS);
+            for instruction in [
+                Instruction::IfEq(offset),
+                Instruction::IfNe(offset),
+                Instruction::IfLt(offset),
+                Instruction::IfGt(offset),
+                Instruction::IfLe(offset),
+                Instruction::IfGe(offset),
+                Instruction::EqZero(offset),
+                Instruction::NeZero(offset),
+                Instruction::LtZero(offset),
+                Instruction::GeZero(offset),
+            ] {
+                assert_round_trip(instruction);
+            }
+        }
+
+        for value in field_values(PRINT_OFFSET_BITS) {
+            for fmt in 0..4 {
+                assert_round_trip(Instruction::Print(
+                    sign_extend(value, PRINT_OFFSET_BITS),
+                    fmt,
+                ));
+            }
+        }
+
+        for instruction in [
+            Instruction::Nop(),
+            Instruction::Input(),
+            Instruction::Add(),
+            Instruction::Sub(),
+            Instruction::Mul(),
+            Instruction::Div(),
+            Instruction::Rem(),
+            Instruction::And(),
+            Instruction::Or(),
+            Instruction::Xor(),
+            Instruction::Lsl(),
+            Instruction::Lsr(),
+            Instruction::Asr(),
+            Instruction::Neg(),
+            Instruction::Not(),
+            Instruction::Dump(),
+        ] {
+            assert_round_trip(instruction);
+        }
+    }
+
+    #[test]
+    fn test_decode_every_opcode_and_function() {
+        // The top byte selects the opcode and function for every instruction, so
+        // walking it covers every valid and invalid combination.
+        for top in 0..=u8::MAX as u32 {
+            for low in [0, 0x0000_0004, 0x0080_0000, 0x00FF_FFFF] {
+                let word = (top << 24) | low;
+                match Instruction::decode(word) {
+                    Ok(instruction) => {
+                        let canonical = instruction.encode();
+                        assert_eq!(Ok(instruction), Instruction::decode(canonical));
+                    }
+                    Err(e) => assert_eq!(word, e.word()),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_field_widths() {
+        // Exit takes the whole low byte, where it used to stop at 0xC
+        assert_eq!(Ok(Instruction::Exit(44)), Instruction::decode(0x0000_002C));
+        // Bit 24 of an if is written as part of the offset but never read
+        assert_eq!(0x83FF_FFFC, Instruction::IfNe(-4).encode());
+        assert_eq!(Ok(Instruction::IfNe(-4)), Instruction::decode(0x83FF_FFFC));
+        assert_eq!(Ok(Instruction::IfNe(-4)), Instruction::decode(0x82FF_FFFC));
+        assert_eq!(Ok(Instruction::EqZero(8)), Instruction::decode(0x9100_0008));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(
+            Err(DecodeError::UnknownOpcode {
+                word: 0xA000_0000,
+                opcode: 10
+            }),
+            Instruction::decode(0xA000_0000)
+        );
+        assert_eq!(
+            Err(DecodeError::UnknownOpcode {
+                word: 0xB000_0000,
+                opcode: 11
+            }),
+            Instruction::decode(0xB000_0000)
+        );
+        assert_eq!(
+            Err(DecodeError::UnknownFunction {
+                word: 0x0300_0000,
+                opcode: 0,
+                func: 0b0011
+            }),
+            Instruction::decode(0x0300_0000)
+        );
+        assert_eq!(
+            Err(DecodeError::UnknownFunction {
+                word: 0x2A00_0000,
+                opcode: 2,
+                func: 0b1010
+            }),
+            Instruction::decode(0x2A00_0000)
+        );
+        assert!(Instruction::decode(0x8C00_0000).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!("swap    8 4", Instruction::Swap(2, 1).to_string());
+        assert_eq!("printo  -12", Instruction::Print(-3, 3).to_string());
+        assert_eq!("goto    -8", Instruction::Goto(-8).to_string());
+        assert_eq!("ifeq    +12", Instruction::IfEq(12).to_string());
+        assert_eq!("add", Instruction::Add().to_string());
+    }
+
+    #[test]
+    fn test_encode_marz() {
+        let binary = include_bytes!("../marz/all.v");
+
+        for word in binary[4..]
+            .chunks(4)
+            .map(|x| u32::from_le_bytes(<[u8; 4]>::try_from(x).unwrap()))
+        {
+            assert_eq!(word, Instruction::decode(word).unwrap().encode());
+        }
+    }
+}
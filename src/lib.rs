@@ -0,0 +1,36 @@
+// A virtual machine for the COSC 365 stack machine ISA, plus an assembler and
+// disassembler for its .v program images.
+//
+// The binary in main.rs is a thin command line wrapper around this library.
+
+pub mod asm;
+pub mod backtrace;
+mod capture;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+mod escape;
+pub mod gdb;
+pub mod instruction;
+mod json;
+pub mod loader;
+mod machine;
+pub mod profile;
+pub mod symbols;
+pub mod trace;
+pub mod watch;
+
+pub use machine::{
+    Limits, Machine, MachineBuilder, PauseHandle, Protection, RunOutcome, StepOutcome,
+};
+
+/// The magic word every .v image starts with (0xdeadbeef as stored on disk)
+pub const MAGIC: u32 = 0xefbe_adde;
+
+/// Memory size, in words, unless configured otherwise
+pub const DEFAULT_MEMORY_WORDS: usize = 1024;
+
+/// Largest memory size, in words, that a 32 bit signed pc and sp can address
+pub const MAX_MEMORY_WORDS: usize = i32::MAX as usize;
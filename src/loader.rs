@@ -0,0 +1,442 @@
+// Reads and validates .v program images
+//
+// An image is a sequence of little-endian 32 bit words. The first word is the
+// magic (the bytes de ad be ef). Legacy images follow it directly with code,
+// which is loaded at address 0 and entered at address 0.
+//
+// Newer images put a header after the magic. It starts with HEADER_TAG, which
+// uses an unassigned opcode, so it can never be the first instruction of a
+// legacy image that runs. All addresses and lengths are in words.
+//
+//   word 0      magic
+//   word 1      HEADER_TAG
+//   word 2      version
+//   word 3      flags (extensions the program needs)
+//   word 4      entry pc
+//   word 5      section count N
+//   N x 3 words section table: kind, load address, length
+//   ...         section contents, in table order
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::MAGIC;
+
+pub const HEADER_TAG: u32 = 0xB000_4856;
+
+/// Newest header version this machine understands
+pub const VERSION: u32 = 1;
+
+/// Flags this machine supports. No extensions are defined yet, so any set
+/// flag means the image needs a newer machine.
+pub const SUPPORTED_FLAGS: u32 = 0;
+
+const HEADER_WORDS: usize = 5;
+const SECTION_ENTRY_WORDS: usize = 3;
+
+/// A validated program image, ready to be copied into memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    /// Header version, 0 for legacy headerless images
+    pub version: u32,
+    pub flags: u32,
+    /// Word index execution starts at
+    pub entry: usize,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub kind: SectionKind,
+    /// Word index the section is loaded at
+    pub addr: usize,
+    pub words: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Code = 0,
+    /// Read-only data, writes to it fault unless the machine runs with
+    /// `Protection::SelfModify`
+    Data = 1,
+}
+
+impl SectionKind {
+    fn from_integer(val: u32) -> Option<Self> {
+        match val {
+            0 => Some(Self::Code),
+            1 => Some(Self::Data),
+            _ => None,
+        }
+    }
+}
+
+impl Section {
+    /// Word index one past the end of the section
+    pub fn end(&self) -> usize {
+        self.addr + self.words.len()
+    }
+}
+
+impl Image {
+    /// A headerless image whose code is loaded and entered at address 0
+    pub fn legacy(code: Vec<u32>) -> Self {
+        Image {
+            version: 0,
+            flags: 0,
+            entry: 0,
+            sections: vec![Section {
+                kind: SectionKind::Code,
+                addr: 0,
+                words: code,
+            }],
+        }
+    }
+
+    /// Number of words of memory needed to hold every section
+    pub fn extent(&self) -> usize {
+        self.sections.iter().map(Section::end).max().unwrap_or(0)
+    }
+
+    /// Serializes the image (magic included), with a header unless it is a
+    /// legacy image
+    pub fn to_words(&self) -> Vec<u32> {
+        let mut words = vec![MAGIC];
+
+        if self.version == 0 {
+            for section in &self.sections {
+                words.extend(&section.words);
+            }
+            return words;
+        }
+
+        words.extend([
+            HEADER_TAG,
+            self.version,
+            self.flags,
+            self.entry as u32,
+            self.sections.len() as u32,
+        ]);
+        for section in &self.sections {
+            words.extend([
+                section.kind as u32,
+                section.addr as u32,
+                section.words.len() as u32,
+            ]);
+        }
+        for section in &self.sections {
+            words.extend(&section.words);
+        }
+
+        words
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file couldn't be read
+    Io(io::Error),
+    /// There isn't even a magic word
+    Empty,
+    /// The length isn't a whole number of words
+    Misaligned { len: usize },
+    /// The first word isn't the magic
+    BadMagic { found: u32 },
+    /// The magic is there but with its bytes reversed, so the image was written
+    /// big-endian
+    ByteSwappedMagic,
+    /// The code doesn't fit in memory
+    TooLarge { words: usize, capacity: usize },
+    /// The header is newer than this machine
+    UnsupportedVersion { version: u32 },
+    /// The image needs extensions this machine doesn't have
+    UnsupportedFlags { flags: u32 },
+    /// The header or section table is inconsistent
+    BadHeader(&'static str),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "unable to read image: {}", e),
+            LoadError::Empty => write!(f, "image is empty"),
+            LoadError::Misaligned { len } => write!(
+                f,
+                "image is {} bytes, which is not a whole number of 4 byte words",
+                len
+            ),
+            // Shown in file order, so it can be compared against `xxd` output
+            LoadError::BadMagic { found } => write!(
+                f,
+                "magic didn't match 0xdeadbeef (found 0x{:08x})",
+                found.swap_bytes()
+            ),
+            LoadError::ByteSwappedMagic => write!(
+                f,
+                "magic is byte-swapped (found 0xefbeadde), the image was written big-endian"
+            ),
+            LoadError::TooLarge { words, capacity } => write!(
+                f,
+                "image is {} words but memory only holds {}",
+                words, capacity
+            ),
+            LoadError::UnsupportedVersion { version } => write!(
+                f,
+                "image version {} is newer than the supported version {}",
+                version, VERSION
+            ),
+            LoadError::UnsupportedFlags { flags } => write!(
+                f,
+                "image requires unsupported extensions (flags 0x{:x})",
+                flags & !SUPPORTED_FLAGS
+            ),
+            LoadError::BadHeader(msg) => write!(f, "bad image header: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+/// Reads and validates the image at `path` for a machine with `capacity` words
+/// of memory
+pub fn load_file(path: impl AsRef<Path>, capacity: usize) -> Result<Image, LoadError> {
+    load_bytes(&fs::read(path)?, capacity)
+}
+
+/// Validates the raw bytes of an image
+pub fn load_bytes(bytes: &[u8], capacity: usize) -> Result<Image, LoadError> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(LoadError::Misaligned { len: bytes.len() });
+    }
+
+    let words: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect();
+
+    load_words(&words, capacity)
+}
+
+/// Validates an image that has already been split into words (magic included)
+pub fn load_words(words: &[u32], capacity: usize) -> Result<Image, LoadError> {
+    match words.first() {
+        None => return Err(LoadError::Empty),
+        Some(&MAGIC) => (),
+        Some(&word) if word == MAGIC.swap_bytes() => return Err(LoadError::ByteSwappedMagic),
+        Some(&found) => return Err(LoadError::BadMagic { found }),
+    }
+
+    let image = if words.get(1) == Some(&HEADER_TAG) {
+        parse_header(&words[2..])?
+    } else {
+        Image::legacy(words[1..].to_vec())
+    };
+
+    if image.extent() > capacity {
+        return Err(LoadError::TooLarge {
+            words: image.extent(),
+            capacity,
+        });
+    }
+
+    Ok(image)
+}
+
+// Parses everything after the header tag
+fn parse_header(words: &[u32]) -> Result<Image, LoadError> {
+    if words.len() < HEADER_WORDS - 1 {
+        return Err(LoadError::BadHeader("truncated header"));
+    }
+
+    let (version, flags, entry, count) = (words[0], words[1], words[2] as usize, words[3]);
+    if version == 0 || version > VERSION {
+        return Err(LoadError::UnsupportedVersion { version });
+    }
+    if flags & !SUPPORTED_FLAGS != 0 {
+        return Err(LoadError::UnsupportedFlags { flags });
+    }
+
+    let table_len = count as usize * SECTION_ENTRY_WORDS;
+    let table = words[HEADER_WORDS - 1..]
+        .get(..table_len)
+        .ok_or(LoadError::BadHeader("truncated section table"))?;
+    let mut contents = &words[HEADER_WORDS - 1 + table_len..];
+
+    let mut sections: Vec<Section> = Vec::new();
+    for entry in table.chunks_exact(SECTION_ENTRY_WORDS) {
+        let kind = SectionKind::from_integer(entry[0])
+            .ok_or(LoadError::BadHeader("unknown section kind"))?;
+        let (addr, len) = (entry[1] as usize, entry[2] as usize);
+        if len > contents.len() {
+            return Err(LoadError::BadHeader(
+                "section runs past the end of the image",
+            ));
+        }
+
+        let section = Section {
+            kind,
+            addr,
+            words: contents[..len].to_vec(),
+        };
+        if sections
+            .iter()
+            .any(|s| section.addr < s.end() && s.addr < section.end())
+        {
+            return Err(LoadError::BadHeader("sections overlap"));
+        }
+
+        contents = &contents[len..];
+        sections.push(section);
+    }
+
+    if !contents.is_empty() {
+        return Err(LoadError::BadHeader(
+            "trailing words after the last section",
+        ));
+    }
+
+    if !sections
+        .iter()
+        .any(|s| s.kind == SectionKind::Code && (s.addr..s.end()).contains(&entry))
+    {
+        return Err(LoadError::BadHeader(
+            "entry point is not inside a code section",
+        ));
+    }
+
+    Ok(Image {
+        version,
+        flags,
+        entry,
+        sections,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_marz() {
+        let image = load_bytes(include_bytes!("../marz/str.v"), 1024).unwrap();
+
+        assert_eq!(0, image.version);
+        assert_eq!(0, image.entry);
+        assert_eq!(28, image.sections[0].words.len());
+        assert_eq!(0xf00a_2164, image.sections[0].words[0]);
+    }
+
+    fn headered() -> Image {
+        Image {
+            version: VERSION,
+            flags: 0,
+            entry: 2,
+            sections: vec![
+                Section {
+                    kind: SectionKind::Code,
+                    addr: 0,
+                    words: vec![0x0200_0000, 0x0000_0001, 0x0000_0000],
+                },
+                Section {
+                    kind: SectionKind::Data,
+                    addr: 16,
+                    words: vec![0x1234_5678],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let image = headered();
+        let words = image.to_words();
+
+        assert_eq!(&[MAGIC, HEADER_TAG, VERSION], &words[..3]);
+        assert_eq!(image, load_words(&words, 1024).unwrap());
+        assert!(matches!(
+            load_words(&words, 16),
+            Err(LoadError::TooLarge {
+                words: 17,
+                capacity: 16
+            })
+        ));
+    }
+
+    #[test]
+    fn test_header_errors() {
+        let check = |image: Image, expected: fn(&LoadError) -> bool| {
+            let err = load_words(&image.to_words(), 1024).unwrap_err();
+            assert!(expected(&err), "{}", err);
+        };
+
+        let mut image = headered();
+        image.version = VERSION + 1;
+        check(image, |e| matches!(e, LoadError::UnsupportedVersion { .. }));
+
+        let mut image = headered();
+        image.flags = 0x8000_0000;
+        check(image, |e| matches!(e, LoadError::UnsupportedFlags { .. }));
+
+        let mut image = headered();
+        image.entry = 16;
+        check(image, |e| matches!(e, LoadError::BadHeader(_)));
+
+        let mut image = headered();
+        image.sections[1].addr = 2;
+        check(image, |e| matches!(e, LoadError::BadHeader(_)));
+
+        let mut words = headered().to_words();
+        words.pop();
+        assert!(matches!(
+            load_words(&words, 1024),
+            Err(LoadError::BadHeader(_))
+        ));
+        assert!(matches!(
+            load_words(&[MAGIC, HEADER_TAG, VERSION], 1024),
+            Err(LoadError::BadHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert!(matches!(load_bytes(&[], 1024), Err(LoadError::Empty)));
+        assert!(matches!(
+            load_bytes(&[0xde, 0xad, 0xbe, 0xef, 0x00], 1024),
+            Err(LoadError::Misaligned { len: 5 })
+        ));
+        assert!(matches!(
+            load_bytes(&[0xef, 0xbe, 0xad, 0xde], 1024),
+            Err(LoadError::ByteSwappedMagic)
+        ));
+        assert!(matches!(
+            load_bytes(&[0x7f, 0x45, 0x4c, 0x46], 1024),
+            Err(LoadError::BadMagic { found: 0x464c_457f })
+        ));
+        assert!(matches!(
+            load_words(&[MAGIC, 0, 0, 0], 2),
+            Err(LoadError::TooLarge {
+                words: 3,
+                capacity: 2
+            })
+        ));
+        assert!(matches!(
+            load_file("marz/does-not-exist.v", 1024),
+            Err(LoadError::Io(_))
+        ));
+    }
+}
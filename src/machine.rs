@@ -0,0 +1,1883 @@
+// The stack machine itself: memory, registers and the instruction interpreter
+
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::backtrace::{Backtrace, CallSite, Frame};
+use crate::coverage::Coverage;
+use crate::error::{ErrorKind, Limit, VmError};
+use crate::instruction::Instruction;
+use crate::loader::{self, Image, LoadError, SectionKind};
+use crate::profile::Profile;
+use crate::symbols::Symbols;
+use crate::trace::{self, TraceFormat, TraceRecord, Tracer};
+use crate::watch::{Access, WatchHit, Watchpoint};
+use crate::{DEFAULT_MEMORY_WORDS, MAX_MEMORY_WORDS};
+
+/// The stack machine. Build one with `MachineBuilder`, load a program with
+/// `Machine::load` or `Machine::load_image`, then `Machine::run` it or go through
+/// it one instruction at a time with `Machine::step`.
+pub struct Machine<R: io::Read, W: io::Write, E: io::Write> {
+    ram: Vec<u32>,
+    sp: i32,
+    pc: i32,
+    input: R,
+    output: W,
+    /// Where `debug` and `dump` output goes, kept apart from what the program prints
+    diagnostics: E,
+    limits: Limits,
+    /// Instructions executed since the program was loaded
+    executed: u64,
+    /// Part of an input line read before the input ran dry, see `StepOutcome::NeedsInput`
+    pending_input: String,
+    trace: Option<Tracer>,
+    /// Output of the instruction being traced
+    trace_output: Vec<u8>,
+    pause: PauseHandle,
+    watchpoints: Vec<Watchpoint>,
+    /// Watched words the instruction being executed touched
+    touched: Vec<Touch>,
+    /// Hits not yet handed out by `step` or reported by `run`
+    watch_hits: Vec<WatchHit>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+    /// Used to say where faults and trace records are in the source
+    symbols: Option<Symbols>,
+    /// Calls that haven't returned yet, outermost first
+    calls: Vec<CallSite>,
+    protection: Protection,
+    /// Where the sections of the loaded image are
+    sections: Vec<(Range<usize>, SectionKind)>,
+}
+
+/// An access to a watched word, see `Machine::touch`
+struct Touch {
+    index: usize,
+    access: Access,
+    old: u32,
+    new: u32,
+}
+
+/// The result of executing a single instruction with `Machine::step`
+#[derive(Debug)]
+pub enum StepOutcome {
+    /// The instruction ran, the machine is ready for the next one
+    Continued,
+    /// The program exited with the given code
+    Exited(u8),
+    /// The input reader returned `io::ErrorKind::WouldBlock`. Nothing was
+    /// executed, step again once more input is available.
+    NeedsInput,
+    /// The instruction failed. The pc still points at it.
+    Faulted(VmError),
+    /// A pause was requested through a `PauseHandle`. Nothing was executed.
+    Paused,
+    /// The instruction ran and touched watched words, see `Machine::watch`
+    Watchpoint(Vec<WatchHit>),
+}
+
+/// How `Machine::run` ended, when the program didn't fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The program exited with the given code
+    Exited(u8),
+    /// A pause was requested through a `PauseHandle`. Calling `run` again
+    /// carries on.
+    Paused,
+}
+
+/// Stops a machine between instructions, from any thread. `run` returns
+/// `RunOutcome::Paused` and `step` returns `StepOutcome::Paused`, after which the
+/// program carries on as if nothing happened.
+#[derive(Debug, Clone, Default)]
+pub struct PauseHandle(Arc<AtomicBool>);
+
+impl PauseHandle {
+    /// Asks the machine to stop before its next instruction
+    pub fn pause(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Withdraws a pause the machine hasn't acted on yet
+    pub fn cancel(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    // Consumes a pending pause
+    fn take(&self) -> bool {
+        self.0.load(Ordering::Relaxed) && self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Bounds on how long a program may run, so a runaway program can't hang its host
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Maximum number of instructions to execute
+    pub fuel: Option<u64>,
+    /// Maximum wall-clock time for a single call to `run`. Time spent blocked on
+    /// input counts, but a blocked read is only noticed once it returns.
+    pub timeout: Option<Duration>,
+}
+
+/// How the machine guards the program it loaded. Code and stack share memory,
+/// so without any guard a deep recursion overwrites the program and then runs
+/// whatever it wrote.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protection {
+    /// Nothing is guarded, for programs that deliberately modify themselves
+    SelfModify,
+    /// A push into any section of the image faults with
+    /// `ErrorKind::StackCollision`, and any other write into a data section
+    /// with `ErrorKind::WriteProtected`
+    #[default]
+    Stack,
+    /// W^X: on top of `Stack`, any write into the image faults with
+    /// `ErrorKind::WriteProtected` and only code sections can be executed
+    ReadOnly,
+}
+
+/// The clock is only read every this many instructions, since it is far slower
+/// than executing an instruction
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// Configures and creates a `Machine`.
+///
+/// By default the machine has `DEFAULT_MEMORY_WORDS` words of memory and is
+/// wired to stdin, stdout and stderr.
+pub struct MachineBuilder<R: io::Read, W: io::Write, E: io::Write> {
+    memory_words: usize,
+    input: R,
+    output: W,
+    diagnostics: E,
+    limits: Limits,
+    trace: Option<Tracer>,
+    pause: PauseHandle,
+    profile: bool,
+    coverage: bool,
+    protection: Protection,
+}
+
+/// What the machine does after executing an instruction
+enum Flow {
+    /// Move on to the next instruction
+    Next,
+    /// The instruction already moved the pc
+    Jump,
+    /// The program exited with the given code
+    Exit(u8),
+    /// The instruction has to wait for input, the pc didn't move
+    NeedsInput,
+    /// A pause was requested, nothing was executed
+    Paused,
+}
+
+impl MachineBuilder<io::Stdin, io::Stdout, io::Stderr> {
+    pub fn new() -> Self {
+        MachineBuilder {
+            memory_words: DEFAULT_MEMORY_WORDS,
+            input: io::stdin(),
+            output: io::stdout(),
+            diagnostics: io::stderr(),
+            limits: Limits::default(),
+            trace: None,
+            pause: PauseHandle::default(),
+            profile: false,
+            coverage: false,
+            protection: Protection::default(),
+        }
+    }
+}
+
+impl Default for MachineBuilder<io::Stdin, io::Stdout, io::Stderr> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: io::Read, W: io::Write, E: io::Write> MachineBuilder<R, W, E> {
+    /// Memory size in words. The stack starts at the top of memory.
+    pub fn memory_words(mut self, words: usize) -> Self {
+        self.memory_words = words;
+        self
+    }
+
+    /// Replaces every limit at once
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Stops the program with `ErrorKind::LimitExceeded` after this many instructions
+    pub fn fuel(mut self, instructions: u64) -> Self {
+        self.limits.fuel = Some(instructions);
+        self
+    }
+
+    /// Stops the program with `ErrorKind::LimitExceeded` once `run` has taken this long
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.limits.timeout = Some(timeout);
+        self
+    }
+
+    /// Records every executed instruction to `out`
+    pub fn trace(mut self, out: impl io::Write + 'static, format: TraceFormat) -> Self {
+        self.trace = Some(Tracer::new(Box::new(out), format));
+        self
+    }
+
+    /// Counts executions per instruction and per function, see `Machine::profile`
+    pub fn profile(mut self) -> Self {
+        self.profile = true;
+        self
+    }
+
+    /// Counts executions per instruction and which way conditionals go, see
+    /// `Machine::coverage`
+    pub fn coverage(mut self) -> Self {
+        self.coverage = true;
+        self
+    }
+
+    /// How the loaded program is guarded against the stack and against itself
+    pub fn protection(mut self, protection: Protection) -> Self {
+        self.protection = protection;
+        self
+    }
+
+    /// Lets `handle` pause the machine, see `Machine::pause_handle`
+    pub fn pause_handle(mut self, handle: PauseHandle) -> Self {
+        self.pause = handle;
+        self
+    }
+
+    /// Where `input` and `stinput` read from
+    pub fn input<R2: io::Read>(self, input: R2) -> MachineBuilder<R2, W, E> {
+        MachineBuilder {
+            memory_words: self.memory_words,
+            input,
+            output: self.output,
+            diagnostics: self.diagnostics,
+            limits: self.limits,
+            trace: self.trace,
+            pause: self.pause,
+            profile: self.profile,
+            coverage: self.coverage,
+            protection: self.protection,
+        }
+    }
+
+    /// Where the program's output goes
+    pub fn output<W2: io::Write>(self, output: W2) -> MachineBuilder<R, W2, E> {
+        MachineBuilder {
+            memory_words: self.memory_words,
+            input: self.input,
+            output,
+            diagnostics: self.diagnostics,
+            limits: self.limits,
+            trace: self.trace,
+            pause: self.pause,
+            profile: self.profile,
+            coverage: self.coverage,
+            protection: self.protection,
+        }
+    }
+
+    /// Where diagnostics that aren't part of the program's output go (`debug`
+    /// and `dump`)
+    pub fn diagnostics<E2: io::Write>(self, diagnostics: E2) -> MachineBuilder<R, W, E2> {
+        MachineBuilder {
+            memory_words: self.memory_words,
+            input: self.input,
+            output: self.output,
+            diagnostics,
+            limits: self.limits,
+            trace: self.trace,
+            pause: self.pause,
+            profile: self.profile,
+            coverage: self.coverage,
+            protection: self.protection,
+        }
+    }
+
+    /// Creates the machine with zeroed memory and an empty stack.
+    ///
+    /// Panics if the memory size is 0 or larger than `MAX_MEMORY_WORDS`.
+    pub fn build(self) -> Machine<R, W, E> {
+        assert!(
+            (1..=MAX_MEMORY_WORDS).contains(&self.memory_words),
+            "memory size {} is out of range",
+            self.memory_words
+        );
+
+        Machine {
+            ram: vec![0; self.memory_words],
+            sp: self.memory_words as i32,
+            pc: 0,
+            input: self.input,
+            output: self.output,
+            diagnostics: self.diagnostics,
+            limits: self.limits,
+            executed: 0,
+            pending_input: String::new(),
+            trace: self.trace,
+            trace_output: Vec::new(),
+            pause: self.pause,
+            watchpoints: Vec::new(),
+            touched: Vec::new(),
+            watch_hits: Vec::new(),
+            profile: self.profile.then(|| Profile::new(0, self.memory_words)),
+            coverage: self.coverage.then(|| Coverage::new(self.memory_words)),
+            symbols: None,
+            calls: Vec::new(),
+            protection: self.protection,
+            sections: Vec::new(),
+        }
+    }
+}
+
+impl<R: io::Read, W: io::Write, E: io::Write> Machine<R, W, E> {
+    /// Validates a program (magic word first) and loads it
+    pub fn load(&mut self, program: &[u32]) -> Result<(), VmError> {
+        let image = loader::load_words(program, self.ram.len())?;
+        self.load_image(&image)
+    }
+
+    /// Copies an image into memory and resets the machine. Fails if the image
+    /// doesn't fit in this machine's memory.
+    pub fn load_image(&mut self, image: &Image) -> Result<(), VmError> {
+        if image.extent() > self.ram.len() {
+            return Err(LoadError::TooLarge {
+                words: image.extent(),
+                capacity: self.ram.len(),
+            }
+            .into());
+        }
+
+        for section in &image.sections {
+            self.ram[section.addr..section.end()].clone_from_slice(&section.words);
+        }
+        self.sections = image
+            .sections
+            .iter()
+            .map(|section| (section.addr..section.end(), section.kind))
+            .collect();
+        self.sp = self.ram.len() as i32;
+        self.pc = image.entry as i32;
+        self.executed = 0;
+        self.pending_input.clear();
+        self.calls.clear();
+        if let Some(profile) = &mut self.profile {
+            *profile = Profile::new(image.entry, self.ram.len());
+        }
+        if let Some(coverage) = &mut self.coverage {
+            *coverage = Coverage::new(self.ram.len());
+        }
+
+        Ok(())
+    }
+
+    /// Runs the program until it exits, faults or runs into one of its limits.
+    /// Watchpoint hits are written to the diagnostics as they happen.
+    ///
+    /// Input that would block is an I/O error here, use `step` to wait for it instead.
+    pub fn run(&mut self) -> Result<RunOutcome, VmError> {
+        let started = Instant::now();
+
+        loop {
+            let flow = self.try_step(Some(started))?;
+            for hit in std::mem::take(&mut self.watch_hits) {
+                writeln!(self.diagnostics, "watchpoint: {}", hit)
+                    .map_err(|e| VmError::new(e.into(), self.pc, self.sp, None))?;
+            }
+            match flow {
+                Flow::Next | Flow::Jump => (),
+                Flow::Exit(code) => return Ok(RunOutcome::Exited(code)),
+                Flow::Paused => return Ok(RunOutcome::Paused),
+                Flow::NeedsInput => {
+                    let instruction = self.fetch().ok();
+                    let kind = ErrorKind::Io(io::ErrorKind::WouldBlock.into());
+                    return Err(VmError::new(kind, self.pc, self.sp, instruction));
+                }
+            }
+        }
+    }
+
+    /// Executes a single instruction. The fuel limit applies, the timeout only
+    /// applies to `run`.
+    pub fn step(&mut self) -> StepOutcome {
+        match self.try_step(None) {
+            Ok(Flow::Next | Flow::Jump) if !self.watch_hits.is_empty() => {
+                StepOutcome::Watchpoint(std::mem::take(&mut self.watch_hits))
+            }
+            Ok(Flow::Next | Flow::Jump) => StepOutcome::Continued,
+            Ok(Flow::Exit(code)) => StepOutcome::Exited(code),
+            Ok(Flow::NeedsInput) => StepOutcome::NeedsInput,
+            Ok(Flow::Paused) => StepOutcome::Paused,
+            Err(e) => StepOutcome::Faulted(e),
+        }
+    }
+
+    /// Word index of the next instruction
+    pub fn pc(&self) -> i32 {
+        self.pc
+    }
+
+    /// Word index of the top of the stack, equal to the memory size when the
+    /// stack is empty
+    pub fn sp(&self) -> i32 {
+        self.sp
+    }
+
+    /// The instruction `step` would execute next
+    pub fn next_instruction(&self) -> Result<Instruction, ErrorKind> {
+        self.fetch()
+    }
+
+    /// The words on the stack, top of the stack first
+    pub fn stack(&self) -> &[u32] {
+        &self.ram[self.sp as usize..]
+    }
+
+    /// All of memory, code and stack alike
+    pub fn memory(&self) -> &[u32] {
+        &self.ram
+    }
+
+    /// All of memory, for debuggers that patch code or stack words. Writes
+    /// through here aren't subject to the machine's `Protection`.
+    pub fn memory_mut(&mut self) -> &mut [u32] {
+        &mut self.ram
+    }
+
+    /// Instructions executed since the program was loaded
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// What the program did so far, if the machine was built with
+    /// `MachineBuilder::profile`
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// What the program executed so far, if the machine was built with
+    /// `MachineBuilder::coverage`
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Names faults and trace records by label and source line, see
+    /// `symbols::load`. The symbols stay set across `load`.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = Some(symbols);
+    }
+
+    pub fn symbols(&self) -> Option<&Symbols> {
+        self.symbols.as_ref()
+    }
+
+    /// The calls that haven't returned yet, outermost first. Unlike the return
+    /// addresses on the stack these can't be overwritten by the program.
+    pub fn call_stack(&self) -> &[CallSite] {
+        &self.calls
+    }
+
+    /// The next instruction and the calls that led to it
+    pub fn backtrace(&self) -> Backtrace {
+        self.backtrace_from(self.pc)
+    }
+
+    /// A handle that pauses this machine from elsewhere, e.g. another thread
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause.clone()
+    }
+
+    /// Reports every instruction that reads or writes a word, through
+    /// `StepOutcome::Watchpoint` when stepping and the diagnostics when running.
+    /// Watchpoints stay set across `load`.
+    pub fn watch(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    /// Removes every watchpoint on word `index`, returning false if there were none
+    pub fn unwatch(&mut self, index: usize) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.index != index);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Executes the instruction at pc, checking the timeout against `started` if given
+    fn try_step(&mut self, started: Option<Instant>) -> Result<Flow, VmError> {
+        let result = self.execute_next(started).map_err(|e| self.annotate(e));
+
+        // The trace has to be complete once the program stops, even if the
+        // process exits right after
+        if !matches!(
+            result,
+            Ok(Flow::Next | Flow::Jump | Flow::NeedsInput | Flow::Paused)
+        ) {
+            if let Some(tracer) = &mut self.trace {
+                let flushed = tracer.flush();
+                if result.is_ok() {
+                    flushed.map_err(|e| VmError::new(e.into(), self.pc, self.sp, None))?;
+                }
+            }
+        }
+
+        result
+    }
+
+    fn execute_next(&mut self, started: Option<Instant>) -> Result<Flow, VmError> {
+        // Faults report the state the machine was in when the instruction started
+        let (pc, sp) = (self.pc, self.sp);
+        if self.pause.take() {
+            return Ok(Flow::Paused);
+        }
+        self.check_limits(started)
+            .map_err(|kind| VmError::new(kind, pc, sp, None))?;
+
+        let instruction = self
+            .fetch()
+            .map_err(|kind| VmError::new(kind, pc, sp, None))?;
+
+        let record = self.trace.is_some().then(|| self.trace_record(instruction));
+        let started = self.profile.is_some().then(Instant::now);
+        self.touched.clear();
+        let result = self.execute(instruction);
+        if let (Some(tracer), Some(mut record)) = (&mut self.trace, record) {
+            record.output = std::mem::take(&mut self.trace_output);
+            if !matches!(result, Ok(Flow::NeedsInput)) {
+                tracer
+                    .record(&record)
+                    .map_err(|e| VmError::new(e.into(), pc, sp, Some(instruction)))?;
+            }
+        }
+
+        let flow = result.map_err(|kind| VmError::new(kind, pc, sp, Some(instruction)))?;
+        let hits = self.touched.drain(..).map(|touch| WatchHit {
+            index: touch.index,
+            access: touch.access,
+            old: touch.old,
+            new: touch.new,
+            pc,
+            sp,
+            instruction,
+        });
+        self.watch_hits.extend(hits);
+        if let Some(coverage) = &mut self.coverage {
+            if !matches!(flow, Flow::NeedsInput | Flow::Paused) {
+                coverage.record(pc as usize, instruction, matches!(flow, Flow::Jump));
+            }
+        }
+        match flow {
+            Flow::NeedsInput | Flow::Paused => return Ok(flow),
+            Flow::Next => self.advance(),
+            Flow::Jump | Flow::Exit(_) => (),
+        }
+        if let (Some(profile), Some(started)) = (&mut self.profile, started) {
+            profile.record(pc as usize, instruction, self.pc, started.elapsed());
+        }
+        self.executed += 1;
+
+        Ok(flow)
+    }
+
+    // Adds how the machine got to the error, and where that is in the source if
+    // the symbols know
+    fn annotate(&self, mut e: VmError) -> VmError {
+        if let Some(context) = &mut e.context {
+            context.location = self.locate(context.pc);
+            context.backtrace = self.backtrace_from(context.pc);
+        }
+        e
+    }
+
+    fn backtrace_from(&self, pc: i32) -> Backtrace {
+        let calls = self.calls.iter().rev().map(|call| call.pc);
+        let frames = std::iter::once(pc)
+            .chain(calls)
+            .map(|pc| Frame {
+                pc,
+                location: self.locate(pc),
+            })
+            .collect();
+        Backtrace { frames }
+    }
+
+    fn locate(&self, pc: i32) -> Option<String> {
+        let pc = usize::try_from(pc).ok()?;
+        self.symbols.as_ref()?.locate(pc)
+    }
+
+    // State at the start of `instruction`, which is at pc
+    fn trace_record(&self, instruction: Instruction) -> TraceRecord {
+        let stack = self.stack();
+        TraceRecord {
+            step: self.executed,
+            pc: self.pc,
+            word: self.ram[self.pc as usize],
+            instruction,
+            sp: self.sp,
+            stack: stack[..stack.len().min(trace::STACK_WORDS)].to_vec(),
+            output: Vec::new(),
+            location: self.locate(self.pc),
+        }
+    }
+
+    // Fails if running one more instruction would go over a limit
+    fn check_limits(&self, started: Option<Instant>) -> Result<(), ErrorKind> {
+        if let Some(fuel) = self.limits.fuel {
+            if self.executed >= fuel {
+                return Err(ErrorKind::LimitExceeded {
+                    limit: Limit::Fuel(fuel),
+                    executed: self.executed,
+                });
+            }
+        }
+
+        if let (Some(timeout), Some(started)) = (self.limits.timeout, started) {
+            if self.executed.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && started.elapsed() >= timeout
+            {
+                return Err(ErrorKind::LimitExceeded {
+                    limit: Limit::Timeout(timeout),
+                    executed: self.executed,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<Flow, ErrorKind> {
+        // If the instruction does not explicitly move the PC you can just perform the action.
+        // If an instruction needs to explicitly move the PC you should:
+        // 1. Calculate the new PC
+        // 2. Perform any action
+        // 3. Set the correct PC value
+        // 4. Return `Flow::Jump` to avoid the 4 byte step after the instruction
+        match instruction {
+            Instruction::Exit(code) => return Ok(Flow::Exit(code)),
+            Instruction::Swap(from, to) => {
+                let from = self.slot(from as i32)?;
+                let to = self.slot(to as i32)?;
+                self.check_write(from)?;
+                self.check_write(to)?;
+                let (a, b) = (self.read_word(from), self.read_word(to));
+                self.write_word(from, b);
+                self.write_word(to, a);
+            }
+            Instruction::Nop() => (),
+            Instruction::Input() => {
+                let Some(s) = self.read_line()? else {
+                    return Ok(Flow::NeedsInput);
+                };
+                let s = s.trim().to_string();
+
+                let word = if s.starts_with("0x") || s.starts_with("0X") {
+                    // Parse Hex
+                    u32::from_str_radix(&s[2..], 16).ok()
+                } else if s.starts_with("0b") || s.starts_with("0B") {
+                    // Parse Binary
+                    u32::from_str_radix(&s[2..], 2).ok()
+                } else {
+                    // Parse Decimal
+                    s.parse::<i32>().ok().map(|v| v as u32)
+                };
+
+                self.push(word.ok_or(ErrorKind::MalformedInput(s))?)?;
+            }
+            Instruction::Stinput(max_chars) => {
+                let Some(s) = self.read_line()? else {
+                    return Ok(Flow::NeedsInput);
+                };
+                // Every byte read is a char of its own, so multibyte characters
+                // count (and get cut) byte by byte, like in the reference
+                let mut s = s
+                    .trim()
+                    .chars()
+                    .take(max_chars as usize)
+                    .collect::<String>()
+                    .into_bytes();
+
+                if s.is_empty() {
+                    // The user didn't type anything
+                    self.push(0)?;
+                } else {
+                    if s.len() % 3 != 0 {
+                        let count = 3 - (s.len() % 3);
+                        for _i in 0..count {
+                            s.push(1);
+                        }
+                    }
+
+                    s.reverse();
+
+                    let push_count = s.len() / 3;
+
+                    for i in 0..push_count {
+                        let mut word: u32 = ((s[i * 3] as u32) << 16)
+                            | ((s[i * 3 + 1] as u32) << 8)
+                            | (s[i * 3 + 2] as u32);
+
+                        if i != 0 {
+                            word |= 0x1 << 24;
+                        }
+
+                        self.push(word)?;
+                    }
+                }
+            }
+            Instruction::Debug(value) => {
+                writeln!(self.diagnostics, "Debug: 0x{:06X}", value)?;
+            }
+            Instruction::Pop(offset) => {
+                // The 28 bit offset is a byte count, sign extend it as a word count
+                let words = ((offset << 4) as i32) >> 6;
+                let sp = self.sp as i64 + words as i64;
+                self.sp = sp.clamp(0, self.ram.len() as i64) as i32;
+            }
+            Instruction::Add() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a.wrapping_add(b))?;
+            }
+            Instruction::Sub() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a.wrapping_sub(b))?;
+            }
+            Instruction::Mul() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a.wrapping_mul(b))?;
+            }
+            Instruction::Div() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a.checked_div(b).unwrap_or(0))?;
+            }
+            Instruction::Rem() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a.checked_rem(b).unwrap_or(0))?;
+            }
+            Instruction::And() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a & b)?;
+            }
+            Instruction::Or() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a | b)?;
+            }
+            Instruction::Xor() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a ^ b)?;
+            }
+            // Only the low 5 bits of a shift amount count, like the reference machine
+            Instruction::Lsl() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a.wrapping_shl(b))?;
+            }
+            Instruction::Lsr() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push(a.wrapping_shr(b))?;
+            }
+            Instruction::Asr() => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                self.sp += 2;
+                self.push((a as i32).wrapping_shr(b) as u32)?;
+            }
+            Instruction::Neg() => {
+                let a = self.peek(0)?;
+                self.sp += 1;
+                self.push((a as i32).wrapping_neg() as u32)?;
+            }
+            Instruction::Not() => {
+                let a = self.peek(0)?;
+                self.sp += 1;
+                self.push(!a)?;
+            }
+            Instruction::Stprint(offset) => {
+                let mut actual_offset = self.slot(offset >> 2)?;
+
+                loop {
+                    let bytes = &self.read_word(actual_offset).to_be_bytes();
+                    if bytes[3] != 1 {
+                        self.write_output(&bytes[3..4])?;
+                    }
+                    if bytes[2] != 1 {
+                        self.write_output(&bytes[2..3])?;
+                    }
+                    if bytes[1] != 1 {
+                        self.write_output(&bytes[1..2])?;
+                    }
+
+                    if actual_offset == 0 || bytes[0] == 0 {
+                        break;
+                    }
+
+                    actual_offset += 1;
+                    if actual_offset >= self.ram.len() {
+                        // The string runs off the bottom of the stack
+                        return Err(ErrorKind::StackUnderflow);
+                    }
+                }
+
+                self.output.flush()?;
+            }
+            Instruction::Call(offset) => {
+                self.push((self.pc + 1) as u32)?;
+                self.calls.push(CallSite {
+                    pc: self.pc,
+                    return_to: self.pc + 1,
+                });
+                self.jump(offset);
+                return Ok(Flow::Jump);
+            }
+            Instruction::Return(offset) => {
+                let slot = self.slot(offset >> 2)?;
+                let target = self.read_word(slot) as i32;
+                // A return out of the entry point has no call to check against
+                if let Some(call) = self.calls.last() {
+                    if target != call.return_to {
+                        return Err(ErrorKind::CorruptedReturn {
+                            call: call.pc,
+                            expected: call.return_to,
+                            found: target,
+                        });
+                    }
+                    self.calls.pop();
+                }
+                self.sp = slot as i32 + 1;
+                self.pc = target;
+                return Ok(Flow::Jump);
+            }
+            Instruction::Goto(offset) => {
+                self.jump(offset);
+                return Ok(Flow::Jump);
+            }
+            Instruction::IfEq(offset) => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                if a == b {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::IfNe(offset) => {
+                let b = self.peek(0)?;
+                let a = self.peek(1)?;
+                if a != b {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::IfLt(offset) => {
+                let b = self.peek(0)? as i32;
+                let a = self.peek(1)? as i32;
+                if a < b {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::IfGt(offset) => {
+                let b = self.peek(0)? as i32;
+                let a = self.peek(1)? as i32;
+                if a > b {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::IfLe(offset) => {
+                let b = self.peek(0)? as i32;
+                let a = self.peek(1)? as i32;
+                if a <= b {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::IfGe(offset) => {
+                let b = self.peek(0)? as i32;
+                let a = self.peek(1)? as i32;
+                if a >= b {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::EqZero(offset) => {
+                if self.peek(0)? == 0 {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::NeZero(offset) => {
+                let val = self.peek(0)?;
+                if val != 0 {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::LtZero(offset) => {
+                let val = self.peek(0)? as i32;
+                if val < 0 {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::GeZero(offset) => {
+                let val = self.peek(0)? as i32;
+                if val >= 0 {
+                    self.jump(offset);
+                    return Ok(Flow::Jump);
+                }
+            }
+            Instruction::Dup(offset) => {
+                let slot = self.slot(offset >> 2)?;
+                let val = self.read_word(slot);
+                self.push(val)?;
+            }
+            Instruction::Print(offset, fmt) => {
+                let slot = self.slot(offset)?;
+                let val = self.read_word(slot);
+                let text = match fmt {
+                    0 => format!("{}\n", val as i32),
+                    1 => format!("0x{:X}\n", val),
+                    2 => format!("0b{:b}\n", val),
+                    3 => format!("0o{:o}\n", val),
+                    _ => format!("{}\n", val),
+                };
+                self.write_output(text.as_bytes())?;
+            }
+            Instruction::Dump() => {
+                for i in self.sp as usize..self.ram.len() {
+                    writeln!(self.diagnostics, "{:04x}: {:08x}", i, self.ram[i])?;
+                }
+            }
+            Instruction::Push(val) => self.push(val)?,
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn advance(&mut self) {
+        self.move_pc(1)
+    }
+
+    fn move_pc(&mut self, step: i32) {
+        self.pc += step
+    }
+
+    fn push(&mut self, word: u32) -> Result<(), ErrorKind> {
+        if self.sp <= 0 {
+            return Err(ErrorKind::StackOverflow);
+        }
+        let index = self.sp as usize - 1;
+        if self.protection != Protection::SelfModify && self.in_image(index).is_some() {
+            return Err(ErrorKind::StackCollision {
+                index: index as i32,
+            });
+        }
+
+        self.sp -= 1;
+        self.write_word(self.sp as usize, word);
+        Ok(())
+    }
+
+    // Every read and write the program makes goes through `read_word` and
+    // `write_word`, so watchpoints see them
+    fn read_word(&mut self, index: usize) -> u32 {
+        let value = self.ram[index];
+        self.touch(index, Access::Read, value, value);
+        value
+    }
+
+    fn write_word(&mut self, index: usize, value: u32) {
+        let old = self.ram[index];
+        self.ram[index] = value;
+        self.touch(index, Access::Write, old, value);
+    }
+
+    // Records an access to `index` if it is watched. Repeated accesses by one
+    // instruction are merged, keeping the first old value.
+    fn touch(&mut self, index: usize, access: Access, old: u32, new: u32) {
+        let mut watched = self.watchpoints.iter();
+        if !watched.any(|w| w.index == index && w.access.includes(access)) {
+            return;
+        }
+
+        match self.touched.iter_mut().find(|touch| touch.index == index) {
+            Some(touch) => {
+                if access == Access::Write {
+                    touch.access = Access::Write;
+                }
+                touch.new = new;
+            }
+            None => self.touched.push(Touch {
+                index,
+                access,
+                old,
+                new,
+            }),
+        }
+    }
+
+    // The kind of the image section word `index` is part of, if any
+    fn in_image(&self, index: usize) -> Option<SectionKind> {
+        self.sections
+            .iter()
+            .find(|(range, _)| range.contains(&index))
+            .map(|&(_, kind)| kind)
+    }
+
+    // Fails if `Protection::ReadOnly` keeps the program from writing word `index`
+    fn check_write(&self, index: usize) -> Result<(), ErrorKind> {
+        let protected = match self.protection {
+            Protection::SelfModify => false,
+            Protection::Stack => self.in_image(index) == Some(SectionKind::Data),
+            Protection::ReadOnly => self.in_image(index).is_some(),
+        };
+        match protected {
+            true => Err(ErrorKind::WriteProtected {
+                index: index as i32,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    // Does not move the program counter, use `advance` to move the program counter
+    // This is so we don't have to step backwards when using PC-relative offsets
+    fn fetch(&self) -> Result<Instruction, ErrorKind> {
+        if self.pc < 0 || self.pc as usize >= self.ram.len() {
+            return Err(ErrorKind::OutOfBounds { index: self.pc });
+        }
+        if self.protection == Protection::ReadOnly
+            && self.in_image(self.pc as usize) != Some(SectionKind::Code)
+        {
+            return Err(ErrorKind::NotExecutable { index: self.pc });
+        }
+
+        Instruction::decode(self.ram[self.pc as usize]).map_err(ErrorKind::IllegalInstruction)
+    }
+
+    // Index into ram of the stack slot `offset` words from the top of the stack.
+    // Slots above the top (negative offsets) are allowed as long as they are in ram.
+    fn slot(&self, offset: i32) -> Result<usize, ErrorKind> {
+        let index = self.sp as i64 + offset as i64;
+        if index >= self.ram.len() as i64 {
+            return Err(ErrorKind::StackUnderflow);
+        } else if index < 0 {
+            return Err(ErrorKind::OutOfBounds {
+                index: index as i32,
+            });
+        }
+
+        Ok(index as usize)
+    }
+
+    // Moves the pc by a byte offset. A pc that lands outside of ram is caught by
+    // the next fetch.
+    fn jump(&mut self, offset: i32) {
+        self.pc = self.pc.wrapping_add(offset >> 2);
+    }
+
+    // Reads the value `depth` slots below the top of the stack
+    fn peek(&mut self, depth: i32) -> Result<u32, ErrorKind> {
+        let slot = self.slot(depth)?;
+        Ok(self.read_word(slot))
+    }
+
+    // Everything the program prints goes through here, so traces can record it
+    fn write_output(&mut self, bytes: &[u8]) -> Result<(), ErrorKind> {
+        if self.trace.is_some() {
+            self.trace_output.extend_from_slice(bytes);
+        }
+        self.output.write_all(bytes)?;
+        Ok(())
+    }
+
+    // Reads up to a newline, NUL or the end of input. Returns None if the reader
+    // would block, keeping what was read so far for the next attempt.
+    fn read_line(&mut self) -> Result<Option<String>, ErrorKind> {
+        let mut buf = [0; 1];
+
+        loop {
+            let read = match self.input.read(&mut buf[..]) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            if read == 0 {
+                break;
+            }
+
+            if buf[0] as char == '\n' || buf[0] as char == '\0' {
+                break;
+            }
+
+            self.pending_input.push(buf[0] as char);
+        }
+
+        Ok(Some(std::mem::take(&mut self.pending_input)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::capture::Captured;
+    use std::io::Write;
+
+    #[test]
+    fn construct_machine() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let program = &[0xefbe_adde, 0x0005_0000, 0x0000_0000];
+
+        machine.load(program).unwrap();
+
+        assert_eq!(machine.ram[..program.len() - 1], program[1..]);
+    }
+
+    #[test]
+    fn test_input() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let program = &[0xefbe_adde, 0x0400_0000];
+
+        let input: u32 = 0x45;
+        machine
+            .input
+            .get_mut()
+            .write_all(format!("{:#x}", input).as_bytes())
+            .unwrap();
+
+        machine.load(program).unwrap();
+        machine.run().unwrap();
+
+        let word = machine.ram[machine.sp as usize];
+        assert_eq!(word, input)
+    }
+
+    #[test]
+    fn test_stinput() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let program = &[0xefbe_adde, 0x0500_00FF];
+
+        machine
+            .input
+            .get_mut()
+            .write_all(b"Hello World\n") // This whitespace will be trimmed
+            .unwrap();
+
+        machine.load(program).unwrap();
+        machine.run().unwrap();
+
+        let words = &machine.ram[machine.sp as usize..machine.sp as usize + 4];
+
+        // This weird array is the string in reverse order, grouped into triplets,
+        // and padded with 0/1 depending on if we're at the end of the string
+        assert_eq!(
+            &[0x016c_6548, 0x0120_6f6c, 0x0172_6f57, 0x0001_646c],
+            &words
+        );
+        assert_eq!(1024 - 4, machine.sp);
+    }
+
+    #[test]
+    fn test_stinput_multibyte() {
+        // Each byte of "é" is read as a char of its own, "Ã" and "©"
+        for (max_chars, expected) in [(0xFF, "nÃ©\n"), (2, "nÃ\n")] {
+            let mut machine = MachineBuilder::new()
+                .input(io::Cursor::new("né\n".as_bytes().to_vec()))
+                .output(io::Cursor::new(Vec::new()))
+                .build();
+
+            // stinput, stprint, push '\n', stprint
+            let program = asm::assemble(&format!(
+                "stinput {}\nstprint\nstpush \"\\n\"\nstprint\nexit",
+                max_chars
+            ))
+            .unwrap();
+            machine.load(&program).unwrap();
+            machine.run().unwrap();
+
+            assert_eq!(expected.as_bytes(), &machine.output.get_ref()[..]);
+        }
+    }
+
+    #[test]
+    fn test_stprint() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let program = &[0xefbe_adde, 0x0500_00FF, 0x4000_0000];
+
+        machine.input.get_mut().write_all(b"Hello World!").unwrap();
+
+        machine.load(program).unwrap();
+        machine.run().unwrap();
+
+        let output = machine.output.clone().into_inner();
+        let output_str = String::from_utf8(output).unwrap();
+
+        assert_eq!("Hello World!", output_str);
+    }
+
+    #[test]
+    fn test_push() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let program = &[0xefbe_adde, 0xf000_0045];
+
+        machine.load(program).unwrap();
+        machine.run().unwrap();
+
+        let word = machine.ram[machine.sp as usize];
+
+        assert_eq!(0x45, word);
+        assert_eq!(1023, machine.sp);
+    }
+
+    #[test]
+    fn test_push_negative() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        // Push -4
+        let program = &[0xefbe_adde, 0xffff_fffc];
+
+        machine.load(program).unwrap();
+        machine.run().unwrap();
+
+        let word = i32::from_ne_bytes(machine.ram[machine.sp as usize].to_ne_bytes());
+
+        assert_eq!(-4, word);
+        assert_eq!(1023, machine.sp);
+    }
+
+    #[test]
+    fn test_pop() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let program = &[0xefbe_adde, 0xf000_0045, 0x1000_0004];
+
+        machine.load(program).unwrap();
+        machine.run().unwrap();
+
+        assert_eq!(1024, machine.sp);
+    }
+
+    #[test]
+    fn test_stinput_marz() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let binary = include_bytes!("../marz/stinput.v");
+
+        // This takes the [u8] that is the file, chunks it into quads,
+        // then returns an array of u32 values
+        let program: Vec<_> = binary
+            .chunks(4)
+            .map(|x| u32::from_le_bytes(<[u8; 4]>::try_from(x).unwrap()))
+            .collect();
+
+        machine.input.get_mut().write_all(b"Hii\n").unwrap();
+
+        machine.load(&program).unwrap();
+        machine.run().unwrap();
+
+        let output = machine.output.clone().into_inner();
+        let output_str = String::from_utf8(output).unwrap();
+
+        assert_eq!("Enter a string: You wrote = 'Hii'\n", output_str);
+    }
+
+    #[test]
+    fn test_illegal_instruction() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        // nop, then an unassigned opcode
+        let program = &[0xefbe_adde, 0x0200_0000, 0xa000_0000];
+
+        machine.load(program).unwrap();
+        let err = machine.run().unwrap_err();
+
+        match err.kind {
+            ErrorKind::IllegalInstruction(e) => assert_eq!(0xa000_0000, e.word()),
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(1, err.context.unwrap().pc);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        // push 1, add
+        let program = &[0xefbe_adde, 0xf000_0001, 0x2000_0000];
+
+        machine.load(program).unwrap();
+        let err = machine.run().unwrap_err();
+
+        assert!(matches!(err.kind, ErrorKind::StackUnderflow));
+        let context = err.context.unwrap();
+        assert_eq!(1, context.pc);
+        assert_eq!(1023, context.sp);
+        assert_eq!(Some(Instruction::Add()), context.instruction);
+    }
+
+    #[test]
+    fn test_fault_location() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let (program, mut symbols) =
+            asm::assemble_with_symbols("main:\n    call sum\nsum:\n    pop 4\n    add\n").unwrap();
+        symbols.file = Some("sum.asm".to_string());
+        machine.set_symbols(symbols);
+        machine.load(&program).unwrap();
+        let err = machine.run().unwrap_err();
+
+        assert_eq!(
+            Some("sum+4 (sum.asm:5)"),
+            err.context.as_ref().unwrap().location.as_deref()
+        );
+        assert!(
+            err.to_string()
+                .starts_with("stack underflow in sum+4 (sum.asm:5) (pc 0x0008"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(b"twelve\n".to_vec()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let program = &[0xefbe_adde, 0x0400_0000];
+
+        machine.load(program).unwrap();
+        let err = machine.run().unwrap_err();
+
+        assert!(matches!(err.kind, ErrorKind::MalformedInput(ref s) if s == "twelve"));
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let err = machine.load(&[]).unwrap_err();
+
+        assert!(matches!(err.kind, ErrorKind::Load(LoadError::Empty)));
+        assert!(err.context.is_none());
+    }
+
+    #[test]
+    fn test_invalid_slots() {
+        // dup 8, print 8, return 40, swap 0 -4 (after pop 1024), stprint on an unterminated string
+        let programs: [&[u32]; 5] = [
+            &[0xefbe_adde, 0xc000_0008],
+            &[0xefbe_adde, 0xf000_0001, 0xd000_0008],
+            &[0xefbe_adde, 0x6000_0028],
+            &[0xefbe_adde, 0x1fff_f000, 0x0100_0fff],
+            &[0xefbe_adde, 0xf100_4142, 0x4000_0000],
+        ];
+
+        for program in programs {
+            let mut machine = MachineBuilder::new()
+                .input(io::Cursor::new(Vec::new()))
+                .output(io::Cursor::new(Vec::new()))
+                .build();
+
+            machine.load(program).unwrap();
+            let err = machine.run().unwrap_err();
+
+            assert!(
+                matches!(
+                    err.kind,
+                    ErrorKind::StackUnderflow | ErrorKind::OutOfBounds { .. }
+                ),
+                "{:x?}: {}",
+                program,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_shift_overflow() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let source = "push 1\npush 40\nlsl\n\
+                      push -1\npush 32\nlsr\n\
+                      push -8\npush 40\nasr\n\
+                      push 1\npush -1\nlsl\n\
+                      exit";
+        machine.load(&asm::assemble(source).unwrap()).unwrap();
+        assert_eq!(RunOutcome::Exited(0), machine.run().unwrap());
+
+        assert_eq!(
+            [0x8000_0000, 0xFFFF_FFFF, 0xFFFF_FFFF, 0x100],
+            machine.ram[DEFAULT_MEMORY_WORDS - 4..]
+        );
+    }
+
+    #[test]
+    fn test_neg_overflow() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let source = "push 1\npush 31\nlsl\nneg\nexit";
+        machine.load(&asm::assemble(source).unwrap()).unwrap();
+        assert_eq!(RunOutcome::Exited(0), machine.run().unwrap());
+
+        assert_eq!([0x8000_0000], machine.ram[DEFAULT_MEMORY_WORDS - 1..]);
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+
+        let program = asm::assemble(".entry start\nexit 1\nstart: exit 2").unwrap();
+        machine.load(&program).unwrap();
+
+        assert_eq!(1, machine.pc);
+        assert_eq!(RunOutcome::Exited(2), machine.run().unwrap());
+    }
+
+    #[test]
+    fn test_memory_size() {
+        // A recursive countdown from 2000, which needs two stack slots per call
+        let program = asm::assemble(
+            "push 2000\ncall count\nexit\ncount:\ndup 4\nifez done\n\
+             push 1\nsub\ncall count\ndone:\npop\nreturn",
+        )
+        .unwrap();
+
+        let mut machine = MachineBuilder::new()
+            .memory_words(1024)
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+        machine.load(&program).unwrap();
+        // The stack grows into the code long before the recursion bottoms out,
+        // the last word of the padded code is the first one it would overwrite
+        let err = machine.run().unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::StackCollision { index: 11 }),
+            "{:?}",
+            err
+        );
+
+        let mut machine = MachineBuilder::new()
+            .memory_words(8192)
+            .input(io::Cursor::new(Vec::new()))
+            .output(io::Cursor::new(Vec::new()))
+            .build();
+        machine.load(&program).unwrap();
+        assert_eq!(RunOutcome::Exited(0), machine.run().unwrap());
+        assert_eq!(8191, machine.sp);
+
+        // Pop clamps to the configured size
+        machine
+            .load(&[0xefbe_adde, 0x1fff_fffc, 0x1000_8000, 0x0000_0000])
+            .unwrap();
+        machine.run().unwrap();
+        assert_eq!(8192, machine.sp);
+    }
+
+    #[test]
+    fn test_protection() {
+        let run = |program: &[u32], memory_words, protection| {
+            let mut machine = MachineBuilder::new()
+                .memory_words(memory_words)
+                .protection(protection)
+                .input(io::Cursor::new(Vec::new()))
+                .output(io::Cursor::new(Vec::new()))
+                .build();
+            machine.load(program).unwrap();
+            machine.run()
+        };
+
+        // The third push lands on `exit 9`
+        let pushes = &asm::assemble("push 1\npush 2\npush 3\nexit 9").unwrap();
+        let err = run(pushes, 6, Protection::Stack).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::StackCollision { index: 3 }),
+            "{:?}",
+            err
+        );
+        // Unguarded, the pushed word runs as `exit 3`
+        assert_eq!(
+            RunOutcome::Exited(3),
+            run(pushes, 6, Protection::SelfModify).unwrap()
+        );
+
+        // `swap` rewrites `exit 1` into `exit 7`, 12 words above the top of the stack
+        let patch = &asm::assemble("push 7\nswap 0 -48\nnop\nexit 1").unwrap();
+        assert_eq!(
+            RunOutcome::Exited(7),
+            run(patch, 16, Protection::Stack).unwrap()
+        );
+        let err = run(patch, 16, Protection::ReadOnly).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::WriteProtected { index: 3 }),
+            "{:?}",
+            err
+        );
+
+        // Data is read-only unless nothing is guarded, `swap` tries to change
+        // the `.word 1` loaded after the padded code
+        let data = &asm::assemble("push 7\nswap 0 -44\nexit\n.data\n.word 1").unwrap();
+        let err = run(data, 16, Protection::Stack).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::WriteProtected { index: 4 }),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            RunOutcome::Exited(0),
+            run(data, 16, Protection::SelfModify).unwrap()
+        );
+
+        // Jumping onto the stack runs the `exit 0` pushed there, unless it's W^X
+        let jump = &[
+            crate::MAGIC,
+            Instruction::Push(0).encode(),
+            Instruction::Goto(4088).encode(),
+        ];
+        assert_eq!(
+            RunOutcome::Exited(0),
+            run(jump, 1024, Protection::Stack).unwrap()
+        );
+        let err = run(jump, 1024, Protection::ReadOnly).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::NotExecutable { index: 1023 }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut machine = MachineBuilder::new()
+            .memory_words(16)
+            .input(io::empty())
+            .output(Vec::new())
+            .diagnostics(Vec::new())
+            .build();
+
+        assert_eq!(16, machine.ram.len());
+        assert_eq!(16, machine.sp);
+
+        machine
+            .load(&asm::assemble("debug 0x2a\nexit").unwrap())
+            .unwrap();
+        machine.run().unwrap();
+        assert_eq!(b"Debug: 0x00002A\n", &machine.diagnostics[..]);
+
+        let too_large = loader::Image::legacy(vec![0; 17]);
+        let err = machine.load_image(&too_large).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ErrorKind::Load(LoadError::TooLarge {
+                words: 17,
+                capacity: 16
+            })
+        ));
+    }
+
+    #[test]
+    fn test_print() {
+        let mut machine = MachineBuilder::new()
+            .input(io::empty())
+            .output(Vec::new())
+            .diagnostics(Vec::new())
+            .build();
+
+        let source =
+            "push -5\nprint\nprinth\nprintb\nprinto\nstpush \"ok\"\nstprint\nprint 4\nexit";
+        machine.load(&asm::assemble(source).unwrap()).unwrap();
+        machine.run().unwrap();
+
+        assert_eq!(
+            "-5\n0xFFFFFFFB\n0b11111111111111111111111111111011\n0o37777777773\nok-5\n",
+            String::from_utf8(machine.output).unwrap()
+        );
+        assert!(machine.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut machine = MachineBuilder::new()
+            .memory_words(8)
+            .input(io::empty())
+            .output(Vec::new())
+            .diagnostics(Vec::new())
+            .build();
+
+        machine
+            .load(&asm::assemble("push 1\npush 0x2a\ndump\nexit").unwrap())
+            .unwrap();
+        machine.run().unwrap();
+
+        assert!(machine.output.is_empty());
+        assert_eq!(
+            "0006: 0000002a\n0007: 00000001\n",
+            String::from_utf8(machine.diagnostics).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let program = asm::assemble("push 1\nloop:\ngoto loop").unwrap();
+
+        let mut machine = MachineBuilder::new()
+            .input(io::empty())
+            .output(Vec::new())
+            .fuel(100)
+            .build();
+        machine.load(&program).unwrap();
+        let err = machine.run().unwrap_err();
+
+        assert!(matches!(
+            err.kind,
+            ErrorKind::LimitExceeded {
+                limit: Limit::Fuel(100),
+                executed: 100
+            }
+        ));
+        assert_eq!(1, err.context.unwrap().pc);
+        assert_eq!(100, machine.executed());
+
+        let mut machine = MachineBuilder::new()
+            .input(io::empty())
+            .output(Vec::new())
+            .timeout(Duration::from_millis(10))
+            .build();
+        machine.load(&program).unwrap();
+        let err = machine.run().unwrap_err();
+
+        assert!(matches!(
+            err.kind,
+            ErrorKind::LimitExceeded {
+                limit: Limit::Timeout(_),
+                ..
+            }
+        ));
+
+        // A program that exits within its budget is unaffected
+        let mut machine = MachineBuilder::new()
+            .input(io::empty())
+            .output(Vec::new())
+            .fuel(2)
+            .build();
+        machine
+            .load(&asm::assemble("push 1\nexit 3").unwrap())
+            .unwrap();
+        assert_eq!(RunOutcome::Exited(3), machine.run().unwrap());
+    }
+
+    // Hands out its chunks one read at a time, with `None` meaning "would block"
+    struct Trickle(Vec<Option<&'static [u8]>>);
+
+    impl io::Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.first_mut() {
+                None => Ok(0),
+                Some(None) => {
+                    self.0.remove(0);
+                    Err(io::ErrorKind::WouldBlock.into())
+                }
+                Some(Some(chunk)) => {
+                    let len = chunk.len().min(buf.len());
+                    buf[..len].copy_from_slice(&chunk[..len]);
+                    *chunk = &chunk[len..];
+                    if chunk.is_empty() {
+                        self.0.remove(0);
+                    }
+                    Ok(len)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_step() {
+        let mut machine = MachineBuilder::new()
+            .input(Trickle(vec![Some(b"1"), None, Some(b"2\n")]))
+            .output(Vec::new())
+            .build();
+
+        machine
+            .load(&asm::assemble("push 5\ninput\nadd\nexit 7").unwrap())
+            .unwrap();
+
+        assert_eq!(Instruction::Push(5), machine.next_instruction().unwrap());
+        assert!(matches!(machine.step(), StepOutcome::Continued));
+        assert_eq!(1, machine.pc());
+        assert_eq!(&[5], machine.stack());
+
+        // The first read blocks halfway through the line, nothing moves
+        assert!(matches!(machine.step(), StepOutcome::NeedsInput));
+        assert_eq!(1, machine.pc());
+        assert_eq!(1, machine.executed());
+
+        assert!(matches!(machine.step(), StepOutcome::Continued));
+        assert_eq!(&[12, 5], machine.stack());
+        assert_eq!(1022, machine.sp());
+
+        assert!(matches!(machine.step(), StepOutcome::Continued));
+        assert_eq!(&[17], machine.stack());
+        assert!(matches!(machine.step(), StepOutcome::Exited(7)));
+        assert_eq!(4, machine.executed());
+    }
+
+    #[test]
+    fn test_pause() {
+        let handle = PauseHandle::default();
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(Vec::new())
+            .pause_handle(handle.clone())
+            .build();
+        machine
+            .load(&asm::assemble("loop: push 1\npop 4\ngoto loop").unwrap())
+            .unwrap();
+
+        // Pause from another thread while it spins
+        let pauser = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            handle.pause();
+        });
+        let outcome = machine.run().unwrap();
+        pauser.join().unwrap();
+        assert_eq!(RunOutcome::Paused, outcome);
+        assert!(machine.executed() > 0);
+
+        // Carrying on doesn't pause again, a cancelled pause never happens
+        let executed = machine.executed();
+        assert!(matches!(machine.step(), StepOutcome::Continued));
+        machine.pause_handle().pause();
+        assert!(matches!(machine.step(), StepOutcome::Paused));
+        assert_eq!(executed + 1, machine.executed());
+        machine.pause_handle().pause();
+        machine.pause_handle().cancel();
+        assert!(matches!(machine.step(), StepOutcome::Continued));
+    }
+
+    #[test]
+    fn test_step_fault() {
+        let mut machine = MachineBuilder::new()
+            .input(io::empty())
+            .output(Vec::new())
+            .build();
+
+        machine.load(&asm::assemble("add").unwrap()).unwrap();
+
+        match machine.step() {
+            StepOutcome::Faulted(err) => {
+                assert!(matches!(err.kind, ErrorKind::StackUnderflow))
+            }
+            outcome => panic!("expected a fault, got {:?}", outcome),
+        }
+        assert_eq!(0, machine.pc());
+    }
+
+    #[test]
+    fn test_trace() {
+        let trace = Captured::default();
+        let mut machine = MachineBuilder::new()
+            .input(io::empty())
+            .output(Vec::new())
+            .trace(trace.clone(), TraceFormat::Json)
+            .build();
+
+        // Call and return move the pc themselves, they must still be recorded
+        let program = asm::assemble("push 3\ncall f\nexit\nf:\nreturn").unwrap();
+        machine.load(&program).unwrap();
+        machine.run().unwrap();
+
+        let trace = String::from_utf8(trace.take()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(4, lines.len(), "{}", trace);
+        assert_eq!(
+            r#"{"step":0,"pc":0,"word":4026531843,"instruction":"push 3","sp":4096,"stack":[],"output":""}"#,
+            lines[0]
+        );
+        assert!(lines[1].contains(r#""instruction":"call +8""#));
+        assert!(lines[2].contains(r#""pc":12,"#) && lines[2].contains(r#""stack":[2,3]"#));
+        assert!(lines[3].contains(r#""step":3,"pc":8,"#));
+    }
+}
@@ -1,837 +1,744 @@
 use std::env::args;
 use std::fs::File;
-use std::io;
-use std::io::Read;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use cosc365_machine::backtrace::Backtrace;
+use cosc365_machine::coverage::Coverage;
+use cosc365_machine::debugger::Debugger;
+use cosc365_machine::error::{ErrorKind, VmError};
+use cosc365_machine::gdb::{self, SessionEnd};
+use cosc365_machine::loader::Image;
+use cosc365_machine::symbols::{self, Symbols};
+use cosc365_machine::trace::{self, TraceFormat};
+use cosc365_machine::watch::{Access, Location, Watchpoint};
+use cosc365_machine::{asm, dap, disasm, loader};
+use cosc365_machine::{
+    Limits, Machine, MachineBuilder, Protection, RunOutcome, DEFAULT_MEMORY_WORDS, MAX_MEMORY_WORDS,
+};
+
+/// Exit status used when the machine itself fails (bad image, fault, I/O error)
+/// rather than the program calling `exit`
+const VM_ERROR_EXIT: i32 = 125;
+
+/// Exit status used when the program is stopped by `--fuel` or `--timeout`,
+/// the same status `timeout(1)` uses
+const LIMIT_EXIT: i32 = 124;
 
 fn main() {
     let a: Vec<String> = args().collect();
-    if a.len() != 2 {
-        println!("Usage: {} <file.v>", &a[0]);
-        return;
-    }
-
-    let mut fl = File::open(&a[1]).expect("No such file or directory");
-    let mut buffer = Vec::new();
-    fl.read_to_end(&mut buffer).expect("Unable to read file");
-
-    // Just an example of it working for now, this will obv change to accept a real file
-    let mut machine = Machine {
-        ram: [0; 1024],
-        sp: 1024,
-        pc: 0,
-        input: io::stdin(),
-        output: io::stdout(),
-    };
-
-    let binary = &buffer;
-
-    // This takes the [u8] that is the file, chunks it into quads,
-    // then returns an array of u32 values
-    let program: Vec<_> = binary
-        .chunks(4)
-        .map(|x| u32::from_le_bytes(<[u8; 4]>::try_from(x).unwrap()))
-        .collect();
-
-    machine.load(&program).unwrap();
-    let exit_code = machine.run().unwrap();
-
-    std::process::exit(exit_code.into());
+    match a.get(1).map(String::as_str) {
+        Some("asm") if a.len() == 3 || a.len() == 4 => assemble_file(&a[2], a.get(3)),
+        Some("disasm") if a.len() == 3 => disassemble_file(&a[2]),
+        Some("trace-diff") if a.len() == 4 => diff_traces(&a[2], &a[3]),
+        Some("dap") if a.len() == 2 => {
+            if let Err(e) = dap::serve(io::stdin(), io::stdout()) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        // A known subcommand with the wrong number of arguments
+        Some("asm" | "disasm" | "trace-diff" | "dap") => {
+            usage(&a[0]);
+            std::process::exit(1);
+        }
+        Some("profile") => match ProfileOptions::parse(&a[2..]) {
+            Ok(options) => profile_file(&options),
+            Err(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+        },
+        Some("coverage") => match CoverageOptions::parse(&a[2..]) {
+            Ok(options) => coverage_file(&options),
+            Err(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+        },
+        Some("debug") => match RunOptions::parse(&a[2..]) {
+            Ok(options) => debug_file(&options),
+            Err(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+        },
+        Some(_) => match RunOptions::parse(&a[1..]) {
+            Ok(options) => run_file(&options),
+            Err(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+        },
+        _ => usage(&a[0]),
+    }
 }
 
-struct Machine<R: io::Read, W: io::Write> {
-    ram: [u32; 1024],
-    sp: i16,
-    pc: i16,
-    input: R,
-    output: W,
+fn usage(program: &str) {
+    println!(
+        "Usage: {} [--memory <words>] [--fuel <instructions>] [--timeout <seconds>]",
+        program
+    );
+    println!("           [--trace <file>] [--trace-format text|json] [--gdb <port|socket>]");
+    println!("           [--watch|--rwatch|--awatch <address|sp - <offset>>]... [--backtrace]");
+    println!("           [--wx|--self-modify] <file.v>");
+    println!("       {} asm <file.asm> [<file.v>]", program);
+    println!("       {} disasm <file.v>", program);
+    println!("       {} trace-diff <trace> <trace>", program);
+    println!("       {} debug [<run options>] <file.v|file.asm>", program);
+    println!(
+        "       {} profile [<run options>] [--folded <file>] <file.v|file.asm>",
+        program
+    );
+    println!(
+        "       {} coverage [<run options>] [--input <file>]... [--lcov <file>]",
+        program
+    );
+    println!("           [--listing <file>] <file.v|file.asm>");
+    println!("       {} dap", program);
 }
 
-impl<R: io::Read, W: io::Write> Machine<R, W> {
-    pub fn load(&mut self, program: &[u32]) -> Result<(), &'static str> {
-        if 0xefbe_adde != program[0] {
-            // Magic didn't match, bail early
-            return Err("Magic didn't match 0xdeadbeef");
-        }
+fn assemble_file(source_path: &str, out_path: Option<&String>) {
+    let fail = |path: &Path, e: io::Error| -> ! {
+        eprintln!("{}: {}", path.display(), e);
+        std::process::exit(1);
+    };
 
-        self.ram[0..program.len() - 1].clone_from_slice(&program[1..]);
-        self.sp = 1024;
-        self.pc = 0;
+    let source =
+        std::fs::read_to_string(source_path).unwrap_or_else(|e| fail(Path::new(source_path), e));
+    let out_path = match out_path {
+        Some(p) => p.into(),
+        None => Path::new(source_path).with_extension("v"),
+    };
 
-        Ok(())
+    match asm::assemble_with_symbols(&source) {
+        Ok((program, mut symbols)) => {
+            std::fs::write(&out_path, asm::to_bytes(&program))
+                .unwrap_or_else(|e| fail(&out_path, e));
+
+            // The sidecar names the source relative to itself, so the two can be
+            // moved together
+            let source = Path::new(source_path);
+            symbols.file = match source.parent() == out_path.parent() {
+                true => source
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned()),
+                false => std::fs::canonicalize(source)
+                    .ok()
+                    .map(|path| path.to_string_lossy().into_owned()),
+            };
+            let sidecar = symbols::sidecar_path(&out_path.to_string_lossy());
+            std::fs::write(&sidecar, symbols.format())
+                .unwrap_or_else(|e| fail(Path::new(&sidecar), e));
+        }
+        Err(e) => {
+            eprintln!("{}:{}", source_path, e);
+            std::process::exit(1);
+        }
     }
+}
 
-    pub fn run(&mut self) -> Result<u8, &'static str> {
-        // If the instruction does not explicitly move the PC you can just perform the action.
-        // If an instruction needs to explicitly move the PC you should:
-        // 1. Calculate the new PC
-        // 2. Perform any action
-        // 3. Set the correct PC value
-        // 4. Call `continue` to avoid the 4 byte step at the bottom of the loop
-        let exit_code;
-        loop {
-            let instruction = self.fetch();
-
-            match instruction {
-                Instruction::Exit(code) => {
-                    exit_code = code;
-                    break;
-                }
-                Instruction::Swap(from, to) => {
-                    let tmp = self.ram[(self.sp + from) as usize];
-                    self.ram[(self.sp + from) as usize] =
-                        self.ram[(self.sp + to) as usize];
-                    self.ram[(self.sp + to) as usize] = tmp;
-                }
-                Instruction::Nop() => (),
-                Instruction::Input() => {
-                    let s = self.read_line()?.trim().to_string();
-                    let word: u32;
-
-                    if s.starts_with("0x") || s.starts_with("0X") {
-                        // Parse Hex
-                        word = u32::from_str_radix(&s.trim()[2..], 16)
-                            .expect("Unable to parse hex literal");
-                    } else if s.starts_with("0b") || s.starts_with("0B") {
-                        // Parse Binary
-                        word = u32::from_str_radix(&s.trim()[2..], 2)
-                            .expect("Unable to parse binary literal");
-                    } else {
-                        // Parse Decimal
-                        word = s.parse::<i32>().expect("Unable to parse decimal literal") as u32;
-                    }
-
-                    self.push(word)?;
-                }
-                Instruction::Stinput(max_chars) => {
-                    let mut s = self.read_line()?;
-                    s = s.trim().to_string();
-
-                    s.truncate(max_chars as usize);
-
-                    if s.len() == 0 {
-                        // The user didn't type anything
-                        self.push(0).unwrap();
-                    } else {
-                        if s.len() % 3 != 0 {
-                            let count = 3 - (s.len() % 3);
-                            for _i in 0..count {
-                                s.push(1 as u8 as char);
-                            }
-                        }
-
-                        let reversed = s.chars().into_iter().rev().collect::<String>();
-
-                        let push_count = reversed.len() / 3;
-
-                        let s_bytes = reversed.as_bytes();
-                        for i in 0..push_count {
-                            let mut word: u32 = ((s_bytes[i * 3] as u32) << 16)
-                                | ((s_bytes[i * 3 + 1] as u32) << 8)
-                                | (s_bytes[i * 3 + 2] as u32);
+fn disassemble_file(path: &str) {
+    // The image is never loaded into a machine, so any size is fine
+    match loader::load_file(path, usize::MAX) {
+        Ok(image) => print!("{}", disasm::disassemble(&image)),
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
 
-                            if i != 0 {
-                                word |= 0x1 << 24;
-                            }
+// Exits with 0 if the traces match and 1 if they diverge, like diff(1)
+fn diff_traces(left_path: &str, right_path: &str) {
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| trace::parse_trace(&text))
+            .unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(2);
+            })
+    };
+    let (left, right) = (read(left_path), read(right_path));
 
-                            self.push(word)?;
-                        }
-                    }
-                }
-                Instruction::Debug(value) => {
-                    eprintln!("Debug: 0x{:06X}", value);
-                }
-                Instruction::Pop(offset) => {
-                    self.sp += (offset >> 2) as i16;
-                    if self.sp >= 1024 {
-                        self.sp = 1024;
-                    } else if self.sp < 0 {
-                        self.sp = 0;
-                    }
-                }
-                Instruction::Add() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(a.wrapping_add(b))?;
-                }
-                Instruction::Sub() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(a.wrapping_sub(b))?;
-                }
-                Instruction::Mul() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(a.wrapping_mul(b))?;
-                }
-                Instruction::Div() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(if b == 0 { 0 } else { a / b })?;
-                }
-                Instruction::Rem() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(if b == 0 { 0 } else { a % b })?;
-                }
-                Instruction::And() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(a & b)?;
-                }
-                Instruction::Or() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(a | b)?;
-                }
-                Instruction::Xor() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(a ^ b)?;
-                }
-                Instruction::Lsl() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(a << b)?;
-                }
-                Instruction::Lsr() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(a >> b)?;
-                }
-                Instruction::Asr() => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    self.sp += 2;
-                    self.push(((a as i32) >> b) as u32)?;
-                }
-                Instruction::Neg() => {
-                    let a = self.ram[self.sp as usize];
-                    self.sp += 1;
-                    self.push((-(a as i32)) as u32)?;
-                }
-                Instruction::Not() => {
-                    let a = self.ram[self.sp as usize];
-                    self.sp += 1;
-                    self.push(!a)?;
-                }
-                Instruction::Stprint(offset) => {
-                    let mut actual_offset = (self.sp + ((offset as i16) >> 2)) as usize;
+    match trace::diff(&left, &right) {
+        None => println!("traces match ({} instructions)", left.len()),
+        Some(divergence) => {
+            println!("{}", divergence);
+            std::process::exit(1);
+        }
+    }
+}
 
-                    loop {
-                        let bytes = &self.ram[actual_offset].to_be_bytes();
-                        if bytes[3] != 1 {
-                            self.output.write(&bytes[3..4]).unwrap();
-                        }
-                        if bytes[2] != 1 {
-                            self.output.write(&bytes[2..3]).unwrap();
-                        }
-                        if bytes[1] != 1 {
-                            self.output.write(&bytes[1..2]).unwrap();
-                        }
+/// Command line options for running a program
+struct RunOptions {
+    path: String,
+    memory_words: usize,
+    limits: Limits,
+    /// File to write an execution trace to
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    /// TCP port or Unix socket to wait for gdb on
+    gdb: Option<String>,
+    /// Words to report accesses to, slots are relative to the empty stack
+    watch: Vec<(Location, Access)>,
+    /// Whether to print a backtrace when the program exits with a nonzero code,
+    /// faults always print one
+    backtrace: bool,
+    protection: Protection,
+    /// Whether to profile the program, set by `profile` rather than a flag
+    profile: bool,
+    /// Whether to record coverage, set by `coverage` rather than a flag
+    coverage: bool,
+}
 
-                        if actual_offset == 0 || bytes[0] == 0 {
-                            break;
+impl RunOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut path = None;
+        let mut memory_words = DEFAULT_MEMORY_WORDS;
+        let mut limits = Limits::default();
+        let mut trace = None;
+        let mut trace_format = TraceFormat::Text;
+        let mut gdb = None;
+        let mut watch = Vec::new();
+        let mut backtrace = false;
+        let mut protection = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--memory" => {
+                    let value = args.next().ok_or("--memory expects a number of words")?;
+                    memory_words = match value.parse() {
+                        Ok(words @ 1..=MAX_MEMORY_WORDS) => words,
+                        _ => {
+                            return Err(format!(
+                                "--memory expects a number of words between 1 and {}, got '{}'",
+                                MAX_MEMORY_WORDS, value
+                            ))
                         }
-
-                        actual_offset += 1;
-                    }
-
-                    self.output.flush().unwrap();
-                }
-                Instruction::Call(offset) => {
-                    self.push((self.pc + 1) as u32)?;
-                    self.pc += (offset >> 2) as i16;
-                    continue;
-                }
-                Instruction::Return(offset) => {
-                    let ret_addr = self.ram[(self.sp + (offset >> 2) as i16) as usize] as i16;
-                    self.sp += (offset >> 2) as i16 + 1;
-                    self.pc = ret_addr;
-                    continue;
-                }
-                Instruction::Goto(offset) => {
-                    self.pc += (offset >> 2) as i16;
-                    continue;
-                }
-                Instruction::IfEq(offset) => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    if a == b {
-                        self.pc += (offset >> 2) as i16;
-                        continue;
-                    }
-                }
-                Instruction::IfNe(offset) => {
-                    let b = self.ram[self.sp as usize];
-                    let a = self.ram[self.sp as usize + 1];
-                    if a != b {
-                        self.pc += (offset >> 2) as i16;
-                        continue;
-                    }
-                }
-                Instruction::IfLt(offset) => {
-                    let b = self.ram[self.sp as usize] as i32;
-                    let a = self.ram[self.sp as usize + 1] as i32;
-                    if a < b {
-                        self.pc += (offset >> 2) as i16;
-                        continue;
-                    }
-                }
-                Instruction::IfGt(offset) => {
-                    let b = self.ram[self.sp as usize] as i32;
-                    let a = self.ram[self.sp as usize + 1] as i32;
-                    if a > b {
-                        self.pc += (offset >> 2) as i16;
-                        continue;
-                    }
-                }
-                Instruction::IfLe(offset) => {
-                    let b = self.ram[self.sp as usize] as i32;
-                    let a = self.ram[self.sp as usize + 1] as i32;
-                    if a <= b {
-                        self.pc += (offset >> 2) as i16;
-                        continue;
-                    }
-                }
-                Instruction::IfGe(offset) => {
-                    let b = self.ram[self.sp as usize] as i32;
-                    let a = self.ram[self.sp as usize + 1] as i32;
-                    if a >= b {
-                        self.pc += (offset >> 2) as i16;
-                        continue;
-                    }
-                }
-                Instruction::EqZero(offset) => {
-                    if self.ram[self.sp as usize] == 0 {
-                        self.pc += offset as i16 >> 2;
-                        continue;
-                    }
-                }
-                Instruction::NeZero(offset) => {
-                    let val = self.ram[self.sp as usize];
-                    if val != 0 {
-                        self.pc += (offset >> 2) as i16;
-                        continue;
+                    };
+                }
+                "--fuel" => {
+                    let value = args
+                        .next()
+                        .ok_or("--fuel expects a number of instructions")?;
+                    let fuel = value.parse().map_err(|_| {
+                        format!("--fuel expects a number of instructions, got '{}'", value)
+                    })?;
+                    limits.fuel = Some(fuel);
+                }
+                "--timeout" => {
+                    let value = args.next().ok_or("--timeout expects a number of seconds")?;
+                    let timeout = value
+                        .parse()
+                        .ok()
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                        .ok_or_else(|| {
+                            format!("--timeout expects a number of seconds, got '{}'", value)
+                        })?;
+                    limits.timeout = Some(timeout);
+                }
+                "--trace" => {
+                    trace = Some(args.next().ok_or("--trace expects a file name")?.clone());
+                }
+                "--trace-format" => {
+                    let value = args.next().ok_or("--trace-format expects text or json")?;
+                    trace_format = TraceFormat::from_name(value).ok_or_else(|| {
+                        format!("--trace-format expects text or json, got '{}'", value)
+                    })?;
+                }
+                "--gdb" => {
+                    let value = args.next().ok_or("--gdb expects a port or socket path")?;
+                    gdb = Some(value.clone());
+                }
+                flag @ ("--watch" | "--rwatch" | "--awatch") => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("{} expects an address or sp - <offset>", flag))?;
+                    let location =
+                        Location::parse(value).map_err(|e| format!("{}: {}", flag, e))?;
+                    let access = match flag {
+                        "--watch" => Access::Write,
+                        "--rwatch" => Access::Read,
+                        _ => Access::ReadWrite,
+                    };
+                    watch.push((location, access));
+                }
+                "--backtrace" => backtrace = true,
+                flag @ ("--wx" | "--self-modify") => {
+                    let chosen = match flag {
+                        "--wx" => Protection::ReadOnly,
+                        _ => Protection::SelfModify,
+                    };
+                    if protection.is_some_and(|p| p != chosen) {
+                        return Err("--wx and --self-modify don't go together".to_string());
                     }
+                    protection = Some(chosen);
                 }
-                Instruction::LtZero(offset) => {
-                    let val = self.ram[self.sp as usize] as i32;
-                    if val < 0 {
-                        self.pc += (offset >> 2) as i16;
-                        continue;
-                    }
-                }
-                Instruction::GeZero(offset) => {
-                    let val = self.ram[self.sp as usize] as i32;
-                    if val >= 0 {
-                        self.pc += (offset >> 2) as i16;
-                        continue;
-                    }
-                }
-                Instruction::Dup(offset) => {
-                    let val = self.ram[(self.sp + ((offset as i16) >> 2)) as usize];
-                    self.push(val)?;
-                }
-                Instruction::Print(offset, fmt) => {
-                    let val = self.ram[(self.sp + (offset as i16)) as usize];
-                    match fmt {
-                        0 => println!("{}", val as i32),
-                        1 => println!("0x{:X}", val),
-                        2 => println!("0b{:b}", val),
-                        3 => println!("0o{:o}", val),
-                        _ => println!("{}", val),
-                    }
-                }
-                Instruction::Dump() => {
-                    for i in self.sp..1024 {
-                        println!("{:04x}: {:08x}", i, self.ram[i as usize]);
-                    }
-                }
-                Instruction::Push(val) => self.push(val).unwrap(),
+                flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+                _ if path.is_some() => return Err(format!("unexpected argument '{}'", arg)),
+                _ => path = Some(arg.clone()),
             }
-
-            self.step();
         }
 
-        Ok(exit_code)
-    }
-
-    fn step(&mut self) {
-        self.move_pc(1)
-    }
-
-    fn move_pc(&mut self, step: i16) {
-        self.pc += step
+        Ok(RunOptions {
+            path: path.ok_or("missing program file")?,
+            memory_words,
+            limits,
+            trace,
+            trace_format,
+            gdb,
+            watch,
+            backtrace,
+            protection: protection.unwrap_or_default(),
+            profile: false,
+            coverage: false,
+        })
     }
+}
 
-    fn push(&mut self, word: u32) -> Result<(), &'static str> {
-        if self.sp <= 0 {
-            return Err("No room left on stack");
-        }
+fn run_file(options: &RunOptions) {
+    let path = &options.path;
+    let mut machine = build_machine(options, io::stdin());
 
-        self.sp -= 1;
-        self.ram[self.sp as usize] = word;
-        Ok(())
+    // Faults are easier to place with symbols, but they aren't worth failing over
+    match symbols::load_sidecar(path) {
+        Ok(Some(symbols)) => machine.set_symbols(symbols),
+        Ok(None) => (),
+        Err(e) => eprintln!("warning: {}", e),
     }
 
-    // Does not move the program counter, use `step` to move the program counter
-    // This is so we don't have to step backwards when using PC-relative offsets
-    fn fetch(&self) -> Instruction {
-        let instruction = self.ram[self.pc as usize];
-        let opcode = Opcode::from_integer(((instruction >> 28) & 0xf) as u8);
+    // `load_image` checks the image against the memory size
+    let result = loader::load_file(path, usize::MAX)
+        .map_err(VmError::from)
+        .and_then(|image| {
+            machine.load_image(&image)?;
+            match &options.gdb {
+                Some(address) => debug_with_gdb(&mut machine, address),
+                None => run_to_exit(&mut machine),
+            }
+        });
 
-        match opcode {
-            Opcode::Miscellaneous => {
-                let func4 = (instruction >> 24) & 0xf;
+    let backtrace = options.backtrace.then(|| machine.backtrace());
+    exit_with(path, result, backtrace);
+}
 
-                match func4 {
-                    0b0000 => Instruction::Exit(instruction as u8 & 0xf),
-                    0b0001 => {
-                        let mut from = (instruction >> 12) as i16 & 0xFFF;
-                        if from >> 11 & 0b1 == 1 {
-                            from = (from as i32 | (0xF000 as i32)) as i16;
-                        }
-                        let mut to = instruction as i16 & 0xFFF;
-                        if to >> 11 & 0b1 == 1 {
-                            to = (to as i32 | (0xF000 as i32)) as i16;
-                        }
-                        Instruction::Swap(from, to)
-                    }
-                    0b0010 => Instruction::Nop(),
-                    0b0100 => Instruction::Input(),
-                    0b0101 => Instruction::Stinput(instruction & 0xFFFFFF),
-                    0b1111 => Instruction::Debug(instruction & 0xFFFFFF),
-                    _ => unreachable!("Not a valid func4 for Opcode 0 ({})", func4),
-                }
-            }
-            Opcode::Pop => {
-                let offset = instruction & 0xFFFFFFF;
+// Nothing on the command line hands out a `PauseHandle`, so a run only ends by
+// exiting or failing
+fn run_to_exit<R: io::Read, W: io::Write, E: io::Write>(
+    machine: &mut Machine<R, W, E>,
+) -> Result<u8, VmError> {
+    match machine.run()? {
+        RunOutcome::Exited(code) => Ok(code),
+        RunOutcome::Paused => unreachable!("the machine was paused without a pause handle"),
+    }
+}
 
-                Instruction::Pop(offset)
-            }
-            Opcode::BinaryArithmetic => {
-                let instr: u32 = (instruction >> 24) & 0xf;
-
-                match instr {
-                    0b0000 => Instruction::Add(),
-                    0b0001 => Instruction::Sub(),
-                    0b0010 => Instruction::Mul(),
-                    0b0011 => Instruction::Div(),
-                    0b0100 => Instruction::Rem(),
-                    0b0101 => Instruction::And(),
-                    0b0110 => Instruction::Or(),
-                    0b0111 => Instruction::Xor(),
-                    0b1000 => Instruction::Lsl(),
-                    0b1001 => Instruction::Lsr(),
-                    0b1011 => Instruction::Asr(),
-                    _ => unreachable!("Not a valid instruction for Opcode 2 ({})", instr),
-                }
+// Exits with the program's exit code, or reports why the machine stopped it.
+// `backtrace` is shown if the program exits with anything but 0.
+fn exit_with(path: &str, result: Result<u8, VmError>, backtrace: Option<Backtrace>) -> ! {
+    match result {
+        Ok(exit_code) => {
+            if let (1.., Some(backtrace)) = (exit_code, backtrace) {
+                eprintln!("{}: exited with code {}", path, exit_code);
+                eprintln!("{}", backtrace);
             }
-            Opcode::UnaryArithmetic => {
-                let instr: u32 = (instruction >> 24) & 0xf;
-
-                match instr {
-                    0b0000 => Instruction::Neg(),
-                    0b0001 => Instruction::Not(),
-                    _ => unreachable!("Not a valid instruction for Opcode 3 ({})", instr),
-                }
+            std::process::exit(exit_code.into())
+        }
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            if let Some(context) = e
+                .context
+                .as_ref()
+                .filter(|c| !c.backtrace.frames.is_empty())
+            {
+                eprintln!("{}", context.backtrace);
             }
-            Opcode::StringPrint => {
-                let offset = instruction & 0xFFFFFFF;
-
-                Instruction::Stprint(offset as i32)
+            match e.kind {
+                ErrorKind::LimitExceeded { .. } => std::process::exit(LIMIT_EXIT),
+                _ => std::process::exit(VM_ERROR_EXIT),
             }
-            Opcode::Call => {
-                let offset = instruction & 0xFFFFFFF;
+        }
+    }
+}
 
-                Instruction::Call(offset as i32)
-            }
-            Opcode::Return => {
-                let offset = instruction & 0xFFFFFFF;
+// Runs the program under gdb's control, carrying on without it if gdb detaches
+fn debug_with_gdb(
+    machine: &mut Machine<io::Stdin, io::Stdout, io::Stderr>,
+    address: &str,
+) -> Result<u8, VmError> {
+    eprintln!("waiting for gdb on {}", address);
+    let session = gdb::accept(address).and_then(|mut stream| gdb::serve(machine, &mut *stream));
+    let end = session.map_err(|e| VmError::new(e.into(), machine.pc(), machine.sp(), None))?;
+
+    match end {
+        SessionEnd::Exited(code) => Ok(code),
+        SessionEnd::Detached => run_to_exit(machine),
+        SessionEnd::Killed => {
+            eprintln!("killed by gdb");
+            std::process::exit(VM_ERROR_EXIT);
+        }
+    }
+}
 
-                Instruction::Return(offset as i32)
-            }
-            Opcode::Goto => {
-                let offset = instruction & 0xFFFFFFF;
+// Commands and the program's input share stdin, both are read a line at a time
+fn debug_file(options: &RunOptions) {
+    let path = &options.path;
+    let mut machine = build_machine(options, io::stdin());
+
+    let loaded = symbols::load(path).and_then(|(image, symbols)| {
+        machine.load_image(&image).map_err(|e| e.to_string())?;
+        Ok(symbols)
+    });
+    let symbols = loaded.unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    });
+
+    let mut debugger = Debugger::new(machine, symbols);
+    if let Err(e) = debugger.run(|line| io::stdin().read_line(line), &mut io::stdout()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
 
-                Instruction::Goto(offset as i32)
-            }
-            Opcode::BinaryIf => {
-                let func2 = (instruction >> 25) & 0b111;
-                let mut offset = instruction as i32 & 0xffffff;
+/// Command line options for profiling a program
+struct ProfileOptions {
+    run: RunOptions,
+    /// File to write folded stacks to
+    folded: Option<String>,
+}
 
-                if offset >> 23 == 1 {
-                    let mask = 0xff << 24;
-                    offset |= mask;
-                }
+impl ProfileOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut folded = None;
+        let mut run_args = Vec::new();
 
-                match func2 {
-                    0b000 => Instruction::IfEq(offset),
-                    0b001 => Instruction::IfNe(offset),
-                    0b010 => Instruction::IfLt(offset),
-                    0b011 => Instruction::IfGt(offset),
-                    0b100 => Instruction::IfLe(offset),
-                    0b101 => Instruction::IfGe(offset),
-                    _ => unreachable!("No binary if with this func2"),
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--folded" => {
+                    folded = Some(args.next().ok_or("--folded expects a file name")?.clone());
                 }
+                _ => run_args.push(arg.clone()),
             }
-            Opcode::UnaryIf => {
-                let func2 = (instruction >> 25) & 0b11;
-                let mut offset = instruction as i32 & 0xffffff;
+        }
 
-                if offset >> 23 == 1 {
-                    let mask = 0xff << 24;
-                    offset |= mask;
-                }
+        let run = RunOptions {
+            profile: true,
+            ..RunOptions::parse(&run_args)?
+        };
+        Ok(ProfileOptions { run, folded })
+    }
+}
 
-                match func2 {
-                    0b00 => Instruction::EqZero(offset),
-                    0b01 => Instruction::NeZero(offset),
-                    0b10 => Instruction::LtZero(offset),
-                    0b11 => Instruction::GeZero(offset),
-                    _ => unreachable!("No unary if with this func2"),
-                }
-            }
-            Opcode::Dup => {
-                let offset = instruction & 0xFFFFFFF;
-                Instruction::Dup(offset as i32)
+// The report goes to stderr once the program stops, so it doesn't mix with the
+// program's output
+fn profile_file(options: &ProfileOptions) {
+    let path = &options.run.path;
+    let mut machine = build_machine(&options.run, io::stdin());
+
+    let loaded = symbols::load(path).and_then(|(image, symbols)| {
+        machine.load_image(&image).map_err(|e| e.to_string())?;
+        machine.set_symbols(symbols.clone());
+        Ok(symbols)
+    });
+    let symbols = loaded.unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    });
+
+    let result = run_to_exit(&mut machine);
+
+    let profile = machine.profile().expect("the machine is built to profile");
+    let written = profile
+        .write_report(&symbols, &mut io::stderr())
+        .and_then(|_| match &options.folded {
+            Some(folded_path) => {
+                let mut out = BufWriter::new(File::create(folded_path)?);
+                profile.write_folded(&symbols, &mut out)?;
+                out.flush()
             }
+            None => Ok(()),
+        });
+    if let Err(e) = written {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 
-            Opcode::Print => {
-                let fmt = instruction & 0b11;
-                let mut offset = (instruction & 0xFFFFFFF) >> 2;
-                // offset can be signed
-                if offset >> 25 == 0b1 {
-                    offset |= 0xFC000000;
-                }
-                Instruction::Print(offset as i32, fmt as i8)
-            }
-            Opcode::Dump => Instruction::Dump(),
-            Opcode::Push => {
-                let mask = 0xF << 28;
-                let mut val = instruction & 0xFFFFFFF;
+    let backtrace = options.run.backtrace.then(|| machine.backtrace());
+    exit_with(path, result, backtrace);
+}
 
-                if val >> 27 == 1 {
-                    val |= mask;
-                }
+/// Command line options for measuring coverage
+struct CoverageOptions {
+    run: RunOptions,
+    /// Files to run the program on, one run each. Without any it runs once on stdin.
+    inputs: Vec<String>,
+    lcov: Option<String>,
+    listing: Option<String>,
+}
 
-                Instruction::Push(val)
+impl CoverageOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut inputs = Vec::new();
+        let mut lcov = None;
+        let mut listing = None;
+        let mut run_args = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--input" => inputs.push(args.next().ok_or("--input expects a file name")?.clone()),
+                "--lcov" => lcov = Some(args.next().ok_or("--lcov expects a file name")?.clone()),
+                "--listing" => {
+                    listing = Some(args.next().ok_or("--listing expects a file name")?.clone());
+                }
+                _ => run_args.push(arg.clone()),
             }
         }
-    }
 
-    fn read_line(&mut self) -> Result<String, &'static str> {
-        let mut s = String::new();
-        let mut buf = [0; 1];
-
-        loop {
-            let read = self.input.read(&mut buf[..]).unwrap();
-            if read == 0 {
-                break;
-            }
+        let run = RunOptions {
+            coverage: true,
+            ..RunOptions::parse(&run_args)?
+        };
+        Ok(CoverageOptions {
+            run,
+            inputs,
+            lcov,
+            listing,
+        })
+    }
+}
 
-            if buf[0] as char == '\n' || buf[0] as char == '\0' {
-                break;
+// Runs the program once per input and reports the merged coverage. How each run
+// ended goes to stderr, but doesn't change the exit status: a run that faults is
+// as much a part of the coverage as one that exits.
+fn coverage_file(options: &CoverageOptions) {
+    let path = &options.run.path;
+    let (image, symbols) = symbols::load(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    });
+
+    let mut total = Coverage::new(image.extent());
+    let mut run = |name: &str, input: Box<dyn io::Read>| {
+        let mut machine = build_machine(&options.run, input);
+        machine.set_symbols(symbols.clone());
+        match machine
+            .load_image(&image)
+            .and_then(|_| run_to_exit(&mut machine))
+        {
+            Ok(code) => eprintln!("{}: exited with code {}", name, code),
+            Err(e) => eprintln!("{}: {}", name, e),
+        }
+        total.merge(
+            machine
+                .coverage()
+                .expect("the machine is built for coverage"),
+        );
+    };
+    if options.inputs.is_empty() {
+        run(path, Box::new(io::stdin()));
+    }
+    for input in &options.inputs {
+        match File::open(input) {
+            Ok(file) => run(input, Box::new(io::BufReader::new(file))),
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                std::process::exit(1);
             }
-
-            s.push(buf[0] as char);
         }
+    }
 
-        Ok(s)
+    if let Err(e) = write_coverage(options, &total, &image, &symbols) {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
 }
 
-#[derive(Debug)]
-enum Opcode {
-    Miscellaneous = 0b0000,
-    Pop = 0b0001,
-    BinaryArithmetic = 0b0010,
-    UnaryArithmetic = 0b0011,
-    StringPrint = 0b0100,
-    Call = 0b0101,
-    Return = 0b0110,
-    Goto = 0b0111,
-    BinaryIf = 0b1000,
-    UnaryIf = 0b1001,
-    Dup = 0b1100,
-    Print = 0b1101,
-    Dump = 0b1110,
-    Push = 0b1111,
-}
+// The listing goes to stderr unless a report file is asked for
+fn write_coverage(
+    options: &CoverageOptions,
+    coverage: &Coverage,
+    image: &Image,
+    symbols: &Symbols,
+) -> io::Result<()> {
+    if options.lcov.is_none() && options.listing.is_none() {
+        return coverage.write_listing(image, symbols, &mut io::stderr());
+    }
 
-impl Opcode {
-    fn from_integer(val: u8) -> Self {
-        match val {
-            0 => Self::Miscellaneous,
-            1 => Self::Pop,
-            2 => Self::BinaryArithmetic,
-            3 => Self::UnaryArithmetic,
-            4 => Self::StringPrint,
-            5 => Self::Call,
-            6 => Self::Return,
-            7 => Self::Goto,
-            8 => Self::BinaryIf,
-            9 => Self::UnaryIf,
-            12 => Self::Dup,
-            13 => Self::Print,
-            14 => Self::Dump,
-            15 => Self::Push,
-            _ => unreachable!("I got {} which is not a valid opcode", val),
-        }
+    eprintln!("{}", coverage.summary(image));
+    if let Some(lcov_path) = &options.lcov {
+        let mut out = BufWriter::new(File::create(lcov_path)?);
+        let source = symbols.file.as_ref().unwrap_or(&options.run.path);
+        coverage.write_lcov(image, symbols, source, &mut out)?;
+        out.flush()?;
     }
+    if let Some(listing_path) = &options.listing {
+        let mut out = BufWriter::new(File::create(listing_path)?);
+        coverage.write_listing(image, symbols, &mut out)?;
+        out.flush()?;
+    }
+    Ok(())
 }
 
-#[derive(Debug)]
-enum Instruction {
-    Exit(u8),
-    Swap(i16, i16),
-    Nop(),
-    Input(),
-    Stinput(u32),
-    Debug(u32),
-    Pop(u32),
-    Add(),
-    Sub(),
-    Mul(),
-    Div(),
-    Rem(),
-    And(),
-    Or(),
-    Xor(),
-    Lsl(),
-    Lsr(),
-    Asr(),
-    Neg(),
-    Not(),
-    Stprint(i32),
-    Call(i32),
-    Return(i32),
-    Goto(i32),
-    IfEq(i32),
-    IfNe(i32),
-    IfLt(i32),
-    IfGt(i32),
-    IfLe(i32),
-    IfGe(i32),
-    EqZero(i32),
-    NeZero(i32),
-    LtZero(i32),
-    GeZero(i32),
-    Dup(i32),
-    Print(i32, i8),
-    Dump(),
-    Push(u32),
+fn build_machine<R: io::Read>(
+    options: &RunOptions,
+    input: R,
+) -> Machine<R, io::Stdout, io::Stderr> {
+    let mut builder = MachineBuilder::new()
+        .input(input)
+        .memory_words(options.memory_words)
+        .limits(options.limits)
+        .protection(options.protection);
+    if options.profile {
+        builder = builder.profile();
+    }
+    if options.coverage {
+        builder = builder.coverage();
+    }
+    if let Some(trace_path) = &options.trace {
+        match File::create(trace_path) {
+            Ok(file) => builder = builder.trace(BufWriter::new(file), options.trace_format),
+            Err(e) => {
+                eprintln!("{}: {}", trace_path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let mut machine = builder.build();
+
+    for &(location, access) in &options.watch {
+        match location.index(machine.sp(), machine.memory().len()) {
+            Ok(index) => machine.watch(Watchpoint { index, access }),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    machine
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
-
-    #[test]
-    fn construct_machine() {
-        let mut machine = Machine {
-            ram: [0; 1024],
-            sp: 1024,
-            pc: 0,
-            input: io::Cursor::new(Vec::new()),
-            output: io::Cursor::new(Vec::new()),
-        };
 
-        let program = &[0xefbe_adde, 0x0005_0000, 0x0000_0000];
-
-        machine.load(program).unwrap();
-
-        assert_eq!(machine.ram[..program.len() - 1], program[1..]);
+    fn args(a: &[&str]) -> Vec<String> {
+        a.iter().map(|s| s.to_string()).collect()
     }
 
     #[test]
-    fn test_input() {
-        let mut machine = Machine {
-            ram: [0; 1024],
-            sp: 1024,
-            pc: 0,
-            input: io::Cursor::new(Vec::new()),
-            output: io::Cursor::new(Vec::new()),
-        };
-
-        let program = &[0xefbe_adde, 0x0400_0000];
-
-        let input: u32 = 0x45;
-        machine
-            .input
-            .get_mut()
-            .write(format!("{:#x}", input).as_bytes())
-            .unwrap();
-
-        machine.load(program).unwrap();
-        machine.run().unwrap();
+    fn test_memory_option() {
+        let options = RunOptions::parse(&args(&["--memory", "4096", "prog.v"])).unwrap();
+        assert_eq!("prog.v", options.path);
+        assert_eq!(4096, options.memory_words);
+        assert_eq!(
+            DEFAULT_MEMORY_WORDS,
+            RunOptions::parse(&args(&["prog.v"])).unwrap().memory_words
+        );
 
-        let word = machine.ram[machine.sp as usize];
-        assert_eq!(word, input)
+        assert!(RunOptions::parse(&args(&["--memory", "0", "prog.v"])).is_err());
+        assert!(RunOptions::parse(&args(&["--memory", "prog.v"])).is_err());
     }
 
     #[test]
-    fn test_stinput() {
-        let mut machine = Machine {
-            ram: [0; 1024],
-            sp: 1024,
-            pc: 0,
-            input: io::Cursor::new(Vec::new()),
-            output: io::Cursor::new(Vec::new()),
-        };
-
-        let program = &[0xefbe_adde, 0x0500_00FF];
-
-        machine
-            .input
-            .get_mut()
-            .write(format!("Hello World\n").as_bytes()) // This whitespace will be trimmed
-            .unwrap();
-
-        machine.load(program).unwrap();
-        machine.run().unwrap();
-
-        let words = &machine.ram[machine.sp as usize..machine.sp as usize + 4];
-
-        // This weird array is the string in reverse order, grouped into triplets,
-        // and padded with 0/1 depending on if we're at the end of the string
-        assert_eq!(
-            &[0x016c_6548, 0x0120_6f6c, 0x0172_6f57, 0x0001_646c],
-            &words
-        );
-        assert_eq!(1024 - 4, machine.sp);
+    fn test_unknown_option() {
+        assert!(RunOptions::parse(&args(&["--fast", "prog.v"])).is_err());
     }
 
     #[test]
-    fn test_stprint() {
-        let mut machine = Machine {
-            ram: [0; 1024],
-            sp: 1024,
-            pc: 0,
-            input: io::Cursor::new(Vec::new()),
-            output: io::Cursor::new(Vec::new()),
-        };
-
-        let program = &[0xefbe_adde, 0x0500_00FF, 0x4000_0000];
-
-        machine
-            .input
-            .get_mut()
-            .write(format!("Hello World!").as_bytes())
-            .unwrap();
-
-        machine.load(program).unwrap();
-        machine.run().unwrap();
-
-        let output = machine.output.clone().into_inner();
-        let output_str = String::from_utf8(output).unwrap();
-
-        assert_eq!("Hello World!", output_str);
+    fn test_limit_options() {
+        let options =
+            RunOptions::parse(&args(&["--fuel", "1000", "--timeout", "1.5", "prog.v"])).unwrap();
+        assert_eq!(Some(1000), options.limits.fuel);
+        assert_eq!(Some(Duration::from_millis(1500)), options.limits.timeout);
+        assert!(RunOptions::parse(&args(&["--fuel", "-1", "prog.v"])).is_err());
+        assert!(RunOptions::parse(&args(&["--timeout", "-1", "prog.v"])).is_err());
     }
 
     #[test]
-    fn test_push() {
-        let mut machine = Machine {
-            ram: [0; 1024],
-            sp: 1024,
-            pc: 0,
-            input: io::Cursor::new(Vec::new()),
-            output: io::Cursor::new(Vec::new()),
-        };
-
-        let program = &[0xefbe_adde, 0xf000_0045];
-
-        machine.load(program).unwrap();
-        machine.run().unwrap();
-
-        let word = machine.ram[machine.sp as usize];
-
-        assert_eq!(0x45, word);
-        assert_eq!(1023, machine.sp);
+    fn test_trace_options() {
+        let options = RunOptions::parse(&args(&[
+            "--trace",
+            "t.jsonl",
+            "--trace-format",
+            "json",
+            "p.v",
+        ]))
+        .unwrap();
+        assert_eq!(Some("t.jsonl".to_string()), options.trace);
+        assert_eq!(TraceFormat::Json, options.trace_format);
+        assert!(RunOptions::parse(&args(&["--trace-format", "xml", "p.v"])).is_err());
     }
 
     #[test]
-    fn test_push_negative() {
-        let mut machine = Machine {
-            ram: [0; 1024],
-            sp: 1024,
-            pc: 0,
-            input: io::Cursor::new(Vec::new()),
-            output: io::Cursor::new(Vec::new()),
-        };
-
-        // Push -4
-        let program = &[0xefbe_adde, 0xffff_fffc];
-
-        machine.load(program).unwrap();
-        machine.run().unwrap();
-
-        let word = i32::from_ne_bytes(machine.ram[machine.sp as usize].to_ne_bytes());
-
-        assert_eq!(-4, word);
-        assert_eq!(1023, machine.sp);
+    fn test_gdb_option() {
+        let options = RunOptions::parse(&args(&["--gdb", "1234", "p.v"])).unwrap();
+        assert_eq!(Some("1234".to_string()), options.gdb);
+        assert!(RunOptions::parse(&args(&["p.v", "--gdb"])).is_err());
     }
 
     #[test]
-    fn test_pop() {
-        let mut machine = Machine {
-            ram: [0; 1024],
-            sp: 1024,
-            pc: 0,
-            input: io::Cursor::new(Vec::new()),
-            output: io::Cursor::new(Vec::new()),
-        };
-
-        let program = &[0xefbe_adde, 0xf000_0045, 0x1000_0004];
-
-        machine.load(program).unwrap();
-        machine.run().unwrap();
-
-        assert_eq!(1024, machine.sp);
+    fn test_watch_options() {
+        let options =
+            RunOptions::parse(&args(&["--watch", "sp - 4", "--awatch", "0x10", "p.v"])).unwrap();
+        assert_eq!(
+            vec![
+                (Location::Slot(-4), Access::Write),
+                (Location::Address(0x10), Access::ReadWrite)
+            ],
+            options.watch
+        );
+        assert!(RunOptions::parse(&args(&["--rwatch", "sp + 2", "p.v"])).is_err());
     }
 
     #[test]
-    fn test_stinput_marz() {
-        let mut machine = Machine {
-            ram: [0; 1024],
-            sp: 1024,
-            pc: 0,
-            input: io::Cursor::new(Vec::new()),
-            output: io::Cursor::new(Vec::new()),
-        };
-
-        let binary = include_bytes!("../marz/stinput.v");
-
-        // This takes the [u8] that is the file, chunks it into quads,
-        // then returns an array of u32 values
-        let program: Vec<_> = binary
-            .chunks(4)
-            .map(|x| u32::from_le_bytes(<[u8; 4]>::try_from(x).unwrap()))
-            .collect();
-
-        machine
-            .input
-            .get_mut()
-            .write(format!("Hii\n").as_bytes())
-            .unwrap();
+    fn test_backtrace_option() {
+        assert!(!RunOptions::parse(&args(&["p.v"])).unwrap().backtrace);
+        assert!(
+            RunOptions::parse(&args(&["--backtrace", "p.v"]))
+                .unwrap()
+                .backtrace
+        );
+    }
 
-        machine.load(&program).unwrap();
-        machine.run().unwrap();
+    #[test]
+    fn test_protection_options() {
+        let options = RunOptions::parse(&args(&["p.v"])).unwrap();
+        assert_eq!(Protection::Stack, options.protection);
+        let options = RunOptions::parse(&args(&["--wx", "p.v"])).unwrap();
+        assert_eq!(Protection::ReadOnly, options.protection);
+        let options = RunOptions::parse(&args(&["--self-modify", "p.v"])).unwrap();
+        assert_eq!(Protection::SelfModify, options.protection);
+        assert!(RunOptions::parse(&args(&["--wx", "--self-modify", "p.v"])).is_err());
+    }
 
-        let output = machine.output.clone().into_inner();
-        let output_str = String::from_utf8(output).unwrap();
+    #[test]
+    fn test_profile_options() {
+        let options =
+            ProfileOptions::parse(&args(&["--fuel", "10", "--folded", "f.txt", "p.asm"])).unwrap();
+        assert_eq!(Some("f.txt".to_string()), options.folded);
+        assert_eq!(Some(10), options.run.limits.fuel);
+        assert!(options.run.profile);
+        assert!(!RunOptions::parse(&args(&["p.v"])).unwrap().profile);
+        assert!(ProfileOptions::parse(&args(&["p.v", "--folded"])).is_err());
+    }
 
-        assert_eq!("Enter a string: You wrote = 'Hii'\n", output_str);
+    #[test]
+    fn test_coverage_options() {
+        let options = CoverageOptions::parse(&args(&[
+            "--input", "a.txt", "--input", "b.txt", "--lcov", "p.info", "p.asm",
+        ]))
+        .unwrap();
+        assert_eq!(vec!["a.txt", "b.txt"], options.inputs);
+        assert_eq!(Some("p.info".to_string()), options.lcov);
+        assert_eq!(None, options.listing);
+        assert!(options.run.coverage);
+        assert!(CoverageOptions::parse(&args(&["--listing"])).is_err());
     }
 }
@@ -0,0 +1,403 @@
+// Instruction-level profiling: how often each instruction ran, and how the work
+// splits up between functions
+//
+// A function is whatever a `call` jumps to, and it lasts until the `return` that
+// isn't matched by a `call` made along the way. Everything before the first call
+// belongs to the program's entry point. Time is wall-clock time per
+// instruction, so an `input` waiting on the user counts against its function.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::instruction::Instruction;
+use crate::symbols::Symbols;
+
+/// Instructions the report lists under "Hot instructions"
+const HOT_INSTRUCTIONS: usize = 20;
+
+/// Counts gathered while a machine runs, see `MachineBuilder::profile`
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// Executions and the last instruction seen at each word index
+    pcs: Vec<(u64, Option<Instruction>)>,
+    /// Executions by mnemonic
+    kinds: BTreeMap<&'static str, u64>,
+    /// By entry point
+    functions: HashMap<usize, FunctionStats>,
+    /// The functions currently running, outermost first
+    stack: Vec<Frame>,
+    /// Instructions executed by each chain of calls, by entry points outermost
+    /// first. The innermost frame's count is still in its `pending`.
+    folded: HashMap<Vec<usize>, u64>,
+    instructions: u64,
+    time: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FunctionStats {
+    pub calls: u64,
+    /// Executed by the function itself
+    pub self_instructions: u64,
+    /// Executed by the function and everything it called. A recursive function
+    /// is only counted once for its outermost call.
+    pub total_instructions: u64,
+    pub self_time: Duration,
+    pub total_time: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct Frame {
+    entry: usize,
+    /// Instructions and time of the whole program when the frame was entered
+    instructions: u64,
+    time: Duration,
+    /// Instructions not yet added to `folded`
+    pending: u64,
+}
+
+impl Profile {
+    /// An empty profile of a program starting at word index `entry`
+    pub fn new(entry: usize, memory_words: usize) -> Self {
+        Profile {
+            pcs: vec![(0, None); memory_words],
+            kinds: BTreeMap::new(),
+            functions: HashMap::new(),
+            stack: vec![Frame {
+                entry,
+                instructions: 0,
+                time: Duration::ZERO,
+                pending: 0,
+            }],
+            folded: HashMap::new(),
+            instructions: 0,
+            time: Duration::ZERO,
+        }
+    }
+
+    /// Records `instruction` at `pc` having executed in `elapsed`, leaving the
+    /// pc at `next_pc`
+    pub fn record(&mut self, pc: usize, instruction: Instruction, next_pc: i32, elapsed: Duration) {
+        let slot = &mut self.pcs[pc];
+        slot.0 += 1;
+        slot.1 = Some(instruction);
+        *self.kinds.entry(instruction.mnemonic()).or_default() += 1;
+        self.instructions += 1;
+        self.time += elapsed;
+
+        let frame = self
+            .stack
+            .last_mut()
+            .expect("the entry frame is never popped");
+        frame.pending += 1;
+        let function = self.functions.entry(frame.entry).or_default();
+        function.self_instructions += 1;
+        function.self_time += elapsed;
+
+        match instruction {
+            Instruction::Call(_) => {
+                // A call that jumps out of memory faults on the next fetch
+                let Ok(entry) = usize::try_from(next_pc) else {
+                    return;
+                };
+                self.fold();
+                self.functions.entry(entry).or_default().calls += 1;
+                self.stack.push(Frame {
+                    entry,
+                    instructions: self.instructions,
+                    time: self.time,
+                    pending: 0,
+                });
+            }
+            // A return out of the entry point is left alone
+            Instruction::Return(_) if self.stack.len() > 1 => {
+                self.fold();
+                let frame = self.stack.pop().expect("checked above");
+                self.close(&frame);
+            }
+            _ => (),
+        }
+    }
+
+    /// Instructions executed in total
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Times the instruction at word index `pc` executed
+    pub fn count(&self, pc: usize) -> u64 {
+        self.pcs.get(pc).map_or(0, |&(count, _)| count)
+    }
+
+    /// Every function that ran, by entry point, with the calls still running
+    /// counted up to now
+    pub fn functions(&self) -> BTreeMap<usize, FunctionStats> {
+        let mut profile = self.clone();
+        while profile.stack.len() > 1 {
+            let frame = profile.stack.pop().expect("checked above");
+            profile.close(&frame);
+        }
+        let entry = profile
+            .stack
+            .pop()
+            .expect("the entry frame is never popped");
+        profile.close(&entry);
+
+        profile.functions.into_iter().collect()
+    }
+
+    /// Writes one line per chain of calls, `main;fact;fact 42`, with the
+    /// instructions executed in the innermost function. This is the folded
+    /// stack format flame graph tools read.
+    pub fn write_folded(&self, symbols: &Symbols, out: &mut impl Write) -> io::Result<()> {
+        let mut folded = self.folded.clone();
+        let top = self.stack.last().expect("the entry frame is never popped");
+        if top.pending > 0 {
+            *folded.entry(self.path()).or_default() += top.pending;
+        }
+
+        let mut lines: Vec<(String, u64)> = folded
+            .into_iter()
+            .map(|(path, count)| {
+                let names: Vec<String> = path.iter().map(|&entry| name(symbols, entry)).collect();
+                (names.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        for (path, count) in lines {
+            writeln!(out, "{} {}", path, count)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a report of the functions, instructions and kinds of instruction
+    /// that did the most work, most first
+    pub fn write_report(&self, symbols: &Symbols, out: &mut impl Write) -> io::Result<()> {
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        let time = |time: Duration| format!("{:.1?}", time);
+
+        writeln!(
+            out,
+            "{} instructions in {}",
+            self.instructions,
+            time(self.time)
+        )?;
+
+        writeln!(out, "\nFunctions, by instructions including calls:")?;
+        writeln!(
+            out,
+            "{:>10} {:>12} {:>7} {:>12} {:>7} {:>10} {:>10}  function",
+            "calls", "total", "%", "self", "%", "total time", "self time"
+        )?;
+        let mut functions: Vec<(usize, FunctionStats)> = self.functions().into_iter().collect();
+        functions
+            .sort_by_key(|&(entry, stats)| (std::cmp::Reverse(stats.total_instructions), entry));
+        for (entry, stats) in functions {
+            writeln!(
+                out,
+                "{:>10} {:>12} {:>6.1}% {:>12} {:>6.1}% {:>10} {:>10}  {}",
+                stats.calls,
+                stats.total_instructions,
+                percent(stats.total_instructions),
+                stats.self_instructions,
+                percent(stats.self_instructions),
+                time(stats.total_time),
+                time(stats.self_time),
+                symbols
+                    .locate(entry)
+                    .unwrap_or_else(|| name(symbols, entry))
+            )?;
+        }
+
+        writeln!(out, "\nHot instructions:")?;
+        let mut pcs: Vec<(usize, u64, Instruction)> = self
+            .pcs
+            .iter()
+            .enumerate()
+            .filter_map(|(pc, &(count, instruction))| Some((pc, count, instruction?)))
+            .collect();
+        pcs.sort_by_key(|&(pc, count, _)| (std::cmp::Reverse(count), pc));
+        for (pc, count, instruction) in pcs.into_iter().take(HOT_INSTRUCTIONS) {
+            let mut location = match symbols.describe(pc) {
+                Some(label) => format!("0x{:04x} <{}>", pc * 4, label),
+                None => format!("0x{:04x}", pc * 4),
+            };
+            if let Some(source) = symbols.source(pc) {
+                location += &format!(" ({})", source);
+            }
+            writeln!(
+                out,
+                "{:>12} {:>6.1}%  {}: {}",
+                count,
+                percent(count),
+                location,
+                instruction
+            )?;
+        }
+
+        writeln!(out, "\nInstructions by kind:")?;
+        let mut kinds: Vec<(&str, u64)> = self.kinds.iter().map(|(&k, &c)| (k, c)).collect();
+        kinds.sort_by_key(|&(kind, count)| (std::cmp::Reverse(count), kind));
+        for (kind, count) in kinds {
+            writeln!(out, "{:>12} {:>6.1}%  {}", count, percent(count), kind)?;
+        }
+        Ok(())
+    }
+
+    // Entry points of the running functions, outermost first
+    fn path(&self) -> Vec<usize> {
+        self.stack.iter().map(|frame| frame.entry).collect()
+    }
+
+    // Moves the innermost frame's pending instructions into `folded`
+    fn fold(&mut self) {
+        let path = self.path();
+        let top = self
+            .stack
+            .last_mut()
+            .expect("the entry frame is never popped");
+        if top.pending > 0 {
+            *self.folded.entry(path).or_default() += top.pending;
+            top.pending = 0;
+        }
+    }
+
+    // Adds a frame that just ended to its function's totals, unless the function
+    // is still running further out
+    fn close(&mut self, frame: &Frame) {
+        if self.stack.iter().any(|outer| outer.entry == frame.entry) {
+            return;
+        }
+        let function = self.functions.entry(frame.entry).or_default();
+        function.total_instructions += self.instructions - frame.instructions;
+        function.total_time += self.time - frame.time;
+    }
+}
+
+/// A function's label, or its byte address without one
+fn name(symbols: &Symbols, entry: usize) -> String {
+    symbols
+        .describe(entry)
+        .unwrap_or_else(|| format!("0x{:04x}", entry * 4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, MachineBuilder};
+
+    // `fact` recurses down to 1, `one` is a leaf called from the base case
+    const PROGRAM: &str = "\
+main:
+    push 3
+    call fact
+    exit
+fact:
+    dup 4
+    push 1
+    ifgt recurse
+    pop 8
+    call one
+    return 0
+recurse:
+    pop 4
+    push 1
+    sub
+    call fact
+    pop 4
+    return 0
+one:
+    return 0
+";
+
+    fn profile(source: &str) -> (Profile, Symbols) {
+        let (program, symbols) = asm::assemble_with_symbols(source).unwrap();
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(Vec::new()))
+            .output(Vec::new())
+            .diagnostics(Vec::new())
+            .profile()
+            .build();
+        machine.load(&program).unwrap();
+        machine.run().unwrap();
+        (machine.profile().unwrap().clone(), symbols)
+    }
+
+    #[test]
+    fn test_functions() {
+        let (profile, symbols) = profile(PROGRAM);
+        let functions = profile.functions();
+        let stats = |label: &str| functions[&symbols.address(label).unwrap()];
+
+        // fact(3) and fact(2) recurse, fact(1) calls one
+        assert_eq!(3, stats("fact").calls);
+        assert_eq!(1, stats("one").calls);
+        assert_eq!(3, stats("main").self_instructions);
+        assert_eq!(2 * 9 + 6, stats("fact").self_instructions);
+        assert_eq!(1, stats("one").self_instructions);
+        assert_eq!(28, profile.instructions());
+        assert_eq!(28, stats("main").total_instructions);
+        assert_eq!(2 * 9 + 6 + 1, stats("fact").total_instructions);
+        assert_eq!(2, profile.count(symbols.address("recurse").unwrap()));
+    }
+
+    #[test]
+    fn test_folded() {
+        let (profile, symbols) = profile(PROGRAM);
+        let mut out = Vec::new();
+        profile.write_folded(&symbols, &mut out).unwrap();
+        assert_eq!(
+            "main 3\n\
+             main;fact 9\n\
+             main;fact;fact 9\n\
+             main;fact;fact;fact 6\n\
+             main;fact;fact;fact;one 1\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        // Without symbols functions are named by address
+        let mut out = Vec::new();
+        profile.write_folded(&Symbols::default(), &mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("0x0000 3\n0x0000;0x000c 9\n"));
+    }
+
+    #[test]
+    fn test_report() {
+        let (profile, symbols) = profile(PROGRAM);
+        let mut out = Vec::new();
+        profile.write_report(&symbols, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+
+        let functions: Vec<&str> = report
+            .lines()
+            .skip_while(|line| !line.starts_with("Functions"))
+            .skip(2)
+            .take(3)
+            .map(|line| line.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(vec!["main", "fact", "one"], functions);
+        assert!(report.contains("0x000c <fact>: dup     4\n"), "{}", report);
+
+        // Functions and instructions point into the source once its file is known
+        let symbols = Symbols {
+            file: Some("fact.asm".to_string()),
+            ..symbols
+        };
+        let mut out = Vec::new();
+        profile.write_report(&symbols, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("  fact (fact.asm:6)\n"), "{}", report);
+        assert!(
+            report.contains("0x000c <fact> (fact.asm:6): dup     4\n"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("\n           6   21.4%  push\n"),
+            "{}",
+            report
+        );
+    }
+}
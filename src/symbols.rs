@@ -0,0 +1,254 @@
+// Symbols recovered from assembly source, so tools can talk about labels and
+// source lines instead of raw addresses
+//
+// `asm` writes the symbols of a program next to its image, `prog.sym` beside
+// `prog.v`. The sidecar is plain text, one directive per line:
+//
+//     source calc.asm
+//     label 0x0000 main
+//     line 0x0000 8
+//
+// Addresses are in bytes, and a `line` covers every word up to the next one.
+// The source path is relative to the sidecar's directory.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::asm;
+use crate::loader::{self, Image};
+
+/// Loads a program for debugging. Assembly source (`.asm`) is assembled in
+/// memory and comes with its symbols, anything else is loaded as an image with
+/// the symbols of its sidecar, if it has one.
+pub fn load(path: &str) -> Result<(Image, Symbols), String> {
+    if !path.ends_with(".asm") {
+        let image = loader::load_file(path, usize::MAX).map_err(|e| e.to_string())?;
+        return Ok((image, load_sidecar(path)?.unwrap_or_default()));
+    }
+
+    let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let (program, mut symbols) = asm::assemble_with_symbols(&source).map_err(|e| e.to_string())?;
+    let image = loader::load_words(&program, usize::MAX).map_err(|e| e.to_string())?;
+    symbols.file = Some(path.to_string());
+    Ok((image, symbols))
+}
+
+/// Path of the sidecar that goes with the image at `path`
+pub fn sidecar_path(path: &str) -> String {
+    Path::new(path)
+        .with_extension("sym")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Reads the sidecar of the image at `path`, `None` if there isn't one. The
+/// source path in the result is usable from the current directory.
+pub fn load_sidecar(path: &str) -> Result<Option<Symbols>, String> {
+    let sidecar = sidecar_path(path);
+    let text = match std::fs::read_to_string(&sidecar) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("{}: {}", sidecar, e)),
+    };
+
+    let mut symbols = Symbols::parse(&text).map_err(|e| format!("{}:{}", sidecar, e))?;
+    let dir = Path::new(&sidecar).parent().unwrap_or(Path::new(""));
+    symbols.file = symbols
+        .file
+        .map(|file| dir.join(file).to_string_lossy().into_owned());
+    Ok(Some(symbols))
+}
+
+/// The labels and source lines of an assembled program
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    /// Word index of every label, by name
+    pub labels: BTreeMap<String, usize>,
+    /// Source line (counting from 1) of the statement starting at each word index
+    pub lines: BTreeMap<usize, usize>,
+    /// Path of the source the lines refer to, if known
+    pub file: Option<String>,
+}
+
+impl Symbols {
+    /// Parses a sidecar written by `format`. Errors start with the line number.
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let bad = |what: &str| format!("{}: {}", i + 1, what);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let rest = rest.trim();
+            if directive == "source" {
+                if rest.is_empty() {
+                    return Err(bad("source expects a path"));
+                }
+                symbols.file = Some(rest.to_string());
+                continue;
+            }
+
+            let (addr, value) = rest
+                .split_once(' ')
+                .ok_or_else(|| bad(&format!("{} expects an address and a value", directive)))?;
+            let addr = addr
+                .strip_prefix("0x")
+                .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+                .filter(|addr| addr.is_multiple_of(4))
+                .ok_or_else(|| bad(&format!("bad address '{}'", addr)))?
+                / 4;
+            let value = value.trim();
+            match directive {
+                "label" => {
+                    symbols.labels.insert(value.to_string(), addr);
+                }
+                "line" => {
+                    let line = value
+                        .parse()
+                        .map_err(|_| bad(&format!("bad line number '{}'", value)))?;
+                    symbols.lines.insert(addr, line);
+                }
+                _ => return Err(bad(&format!("unknown directive '{}'", directive))),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// Writes the symbols as a sidecar, see the top of this file
+    pub fn format(&self) -> String {
+        let mut out = String::new();
+        if let Some(file) = &self.file {
+            let _ = writeln!(out, "source {}", file);
+        }
+        let mut labels: Vec<(&String, &usize)> = self.labels.iter().collect();
+        labels.sort_by_key(|&(name, &addr)| (addr, name));
+        for (name, addr) in labels {
+            let _ = writeln!(out, "label 0x{:04x} {}", addr * 4, name);
+        }
+        for (addr, line) in &self.lines {
+            let _ = writeln!(out, "line 0x{:04x} {}", addr * 4, line);
+        }
+        out
+    }
+
+    /// Word index of a label
+    pub fn address(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).copied()
+    }
+
+    /// Source line of the statement word index `addr` is part of
+    pub fn line(&self, addr: usize) -> Option<usize> {
+        self.lines.range(..=addr).next_back().map(|(_, &line)| line)
+    }
+
+    /// Word index and line of the first statement on `line`, or on the closest
+    /// line after it if `line` has none (a comment, say)
+    pub fn line_address(&self, line: usize) -> Option<(usize, usize)> {
+        self.lines
+            .iter()
+            .filter(|(_, &statement_line)| statement_line >= line)
+            .min_by_key(|(&addr, &statement_line)| (statement_line, addr))
+            .map(|(&addr, &statement_line)| (addr, statement_line))
+    }
+
+    /// Describes word index `addr` relative to the closest label at or before it,
+    /// e.g. `main` or `main+8` (the offset is in bytes)
+    pub fn describe(&self, addr: usize) -> Option<String> {
+        let (name, label_addr) = self
+            .labels
+            .iter()
+            .filter(|(_, &label_addr)| label_addr <= addr)
+            .max_by_key(|(name, &label_addr)| (label_addr, std::cmp::Reverse(*name)))?;
+
+        match (addr - label_addr) * 4 {
+            0 => Some(name.clone()),
+            offset => Some(format!("{}+{}", name, offset)),
+        }
+    }
+
+    /// The source file and line of word index `addr`, e.g. `calc.asm:62`. Only
+    /// known when the symbols name their file.
+    pub fn source(&self, addr: usize) -> Option<String> {
+        Some(format!("{}:{}", self.file.as_ref()?, self.line(addr)?))
+    }
+
+    /// Describes word index `addr` as fully as the symbols allow, e.g.
+    /// `GetInputs+12 (calc.asm:62)`
+    pub fn locate(&self, addr: usize) -> Option<String> {
+        match (self.describe(addr), self.source(addr)) {
+            (Some(name), Some(source)) => Some(format!("{} ({})", name, source)),
+            (name, source) => name.or(source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let mut symbols = Symbols::default();
+        symbols.labels.insert("main".to_string(), 0);
+        symbols.labels.insert("loop".to_string(), 3);
+        symbols.labels.insert("again".to_string(), 3);
+
+        assert_eq!(Some(3), symbols.address("loop"));
+        assert_eq!(None, symbols.address("missing"));
+        assert_eq!(Some("main".to_string()), symbols.describe(0));
+        assert_eq!(Some("main+8".to_string()), symbols.describe(2));
+        // Labels sharing an address resolve to the first by name
+        assert_eq!(Some("again+4".to_string()), symbols.describe(4));
+        assert_eq!(None, Symbols::default().describe(0));
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut symbols = Symbols::default();
+        // A two word statement on line 2, then lines 5 and 6
+        symbols.lines.extend([(0, 2), (2, 5), (3, 6)]);
+
+        assert_eq!(Some(2), symbols.line(1));
+        assert_eq!(Some(6), symbols.line(9));
+        assert_eq!(Some((0, 2)), symbols.line_address(1));
+        assert_eq!(Some((2, 5)), symbols.line_address(3));
+        assert_eq!(Some((3, 6)), symbols.line_address(6));
+        assert_eq!(None, symbols.line_address(7));
+    }
+
+    #[test]
+    fn test_sidecar() {
+        let (_, mut symbols) =
+            asm::assemble_with_symbols("main:\n    push 1\n\nloop:\n    goto loop\n").unwrap();
+        symbols.file = Some("loop.asm".to_string());
+
+        let text = symbols.format();
+        assert_eq!(
+            "source loop.asm\n\
+             label 0x0000 main\n\
+             label 0x0004 loop\n\
+             line 0x0000 2\n\
+             line 0x0004 5\n",
+            text
+        );
+        assert_eq!(Ok(symbols.clone()), Symbols::parse(&text));
+
+        assert_eq!(Some("loop.asm:5".to_string()), symbols.source(1));
+        assert_eq!(Some("loop (loop.asm:5)".to_string()), symbols.locate(1));
+        symbols.file = None;
+        assert_eq!(Some("main".to_string()), symbols.locate(0));
+
+        assert_eq!(
+            Err("2: bad address '0x0002'".to_string()),
+            Symbols::parse("# comment\nlabel 0x0002 main")
+        );
+        assert!(Symbols::parse("line 0x0000 two").is_err());
+        assert!(Symbols::parse("frame 0x0000 1").is_err());
+    }
+}
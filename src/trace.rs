@@ -0,0 +1,508 @@
+// Execution traces: one record per executed instruction
+//
+// A record holds the machine state at the start of the instruction (pc, sp and
+// the top of the stack), the instruction itself and whatever it printed.
+// Addresses are in bytes, like everywhere else we show them. A machine with
+// symbols also names each record's place in the source. Traces are written
+// either as aligned text for people or as JSON Lines for tools, and both can be
+// read back to compare two runs with `diff`.
+
+use std::fmt;
+use std::fmt::Write as _;
+use std::io;
+
+use crate::escape::{quote, unquote};
+use crate::instruction::Instruction;
+
+/// Number of words from the top of the stack each record includes
+pub const STACK_WORDS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One aligned line of text per instruction
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "json" | "jsonl" => Some(TraceFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The state of the machine just before it executed one instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    /// Number of instructions executed before this one
+    pub step: u64,
+    /// Word index of the instruction
+    pub pc: i32,
+    /// The instruction as stored in memory
+    pub word: u32,
+    pub instruction: Instruction,
+    /// Word index of the top of the stack
+    pub sp: i32,
+    /// Up to `STACK_WORDS` words from the top of the stack, top first
+    pub stack: Vec<u32>,
+    /// What the instruction printed
+    pub output: Vec<u8>,
+    /// `pc` in terms of the program's symbols, e.g. `loop+4 (sum.asm:7)`
+    pub location: Option<String>,
+}
+
+impl TraceRecord {
+    /// Formats the record as a single line, without the newline
+    pub fn format(&self, format: TraceFormat) -> String {
+        // Display pads the mnemonic into a column, which only helps in a listing
+        let instruction = self
+            .instruction
+            .to_string()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut line = String::new();
+        match format {
+            TraceFormat::Text => {
+                let stack: Vec<String> = self.stack.iter().map(|w| format!("{:08x}", w)).collect();
+                let _ = write!(
+                    line,
+                    "{:>8}  {:04x}: {:08x}  {:<20} sp {:04x}  [{}]",
+                    self.step,
+                    self.pc as i64 * 4,
+                    self.word,
+                    instruction,
+                    self.sp as i64 * 4,
+                    stack.join(" ")
+                );
+                if !self.output.is_empty() {
+                    let _ = write!(line, "  out {}", quote(&self.output));
+                }
+                if let Some(location) = &self.location {
+                    let _ = write!(line, "  # {}", location);
+                }
+            }
+            TraceFormat::Json => {
+                let stack: Vec<String> = self.stack.iter().map(u32::to_string).collect();
+                let _ = write!(
+                    line,
+                    "{{\"step\":{},\"pc\":{},\"word\":{},\"instruction\":{},\"sp\":{},\"stack\":[{}],\"output\":{}}}",
+                    self.step,
+                    self.pc as i64 * 4,
+                    self.word,
+                    quote(instruction.as_bytes()),
+                    self.sp as i64 * 4,
+                    stack.join(","),
+                    quote(&self.output)
+                );
+                if let Some(location) = &self.location {
+                    line.pop();
+                    let _ = write!(line, ",\"location\":{}}}", quote(location.as_bytes()));
+                }
+            }
+        }
+
+        line
+    }
+
+    /// Parses a line written by `format` in either format
+    pub fn parse(line: &str) -> Result<Self, String> {
+        if line.trim_start().starts_with('{') {
+            parse_json(line)
+        } else {
+            parse_text(line)
+        }
+    }
+}
+
+/// Reads a whole trace, one record per non-empty line
+pub fn parse_trace(text: &str) -> Result<Vec<TraceRecord>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| TraceRecord::parse(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+// Reads a record written in `TraceFormat::Text`
+fn parse_text(line: &str) -> Result<TraceRecord, String> {
+    let bad = || format!("malformed trace line '{}'", line);
+
+    let mut fields = line.split_whitespace();
+    let step = fields.next().and_then(|f| f.parse().ok()).ok_or_else(bad)?;
+    let pc = fields
+        .next()
+        .and_then(|f| f.strip_suffix(':'))
+        .and_then(|f| i64::from_str_radix(f, 16).ok())
+        .ok_or_else(bad)?;
+    let word = fields
+        .next()
+        .and_then(|f| u32::from_str_radix(f, 16).ok())
+        .ok_or_else(bad)?;
+
+    // The instruction text never contains " sp " or brackets
+    let rest = &line[line.find(" sp ").ok_or_else(bad)? + 4..];
+    let (sp, rest) = rest.trim_start().split_once(' ').ok_or_else(bad)?;
+    let sp = i64::from_str_radix(sp, 16).map_err(|_| bad())?;
+    let rest = rest.trim_start().strip_prefix('[').ok_or_else(bad)?;
+    let (stack, rest) = rest.split_once(']').ok_or_else(bad)?;
+    let stack = stack
+        .split_whitespace()
+        .map(|w| u32::from_str_radix(w, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| bad())?;
+
+    let (output, rest) = match rest.trim_start().strip_prefix("out ") {
+        Some(quoted) => unquote(quoted)?,
+        None => (Vec::new(), rest),
+    };
+    let location = match rest.trim_start().strip_prefix("# ") {
+        Some(location) => Some(location.trim_end().to_string()),
+        None if rest.trim().is_empty() => None,
+        None => return Err(bad()),
+    };
+
+    let mut record = record(step, pc, word, sp, stack, output)?;
+    record.location = location;
+    Ok(record)
+}
+
+// Reads a record written in `TraceFormat::Json`. Only the flat objects we write
+// are understood, not JSON in general.
+fn parse_json(line: &str) -> Result<TraceRecord, String> {
+    let bad = |what: &str| format!("malformed trace line ({}) '{}'", what, line);
+
+    let (mut step, mut pc, mut word, mut sp) = (None, None, None, None);
+    let (mut stack, mut output) = (Vec::new(), Vec::new());
+    let mut location = None;
+
+    let mut rest = line
+        .trim()
+        .strip_prefix('{')
+        .ok_or_else(|| bad("expected '{'"))?;
+    loop {
+        let (key, after) = unquote(rest.trim_start())?;
+        rest = after
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or_else(|| bad("expected ':'"))?
+            .trim_start();
+
+        let number = |text: &str| -> (Option<i64>, usize) {
+            let len = text
+                .find(|c: char| !(c.is_ascii_digit() || c == '-'))
+                .unwrap_or(text.len());
+            (text[..len].parse().ok(), len)
+        };
+
+        match &key[..] {
+            b"instruction" => rest = unquote(rest)?.1,
+            b"output" => (output, rest) = unquote(rest)?,
+            b"location" => {
+                let (bytes, after) = unquote(rest)?;
+                location = Some(String::from_utf8(bytes).map_err(|_| bad("bad location"))?);
+                rest = after;
+            }
+            b"stack" => {
+                let (list, after) = rest
+                    .strip_prefix('[')
+                    .and_then(|r| r.split_once(']'))
+                    .ok_or_else(|| bad("expected a list"))?;
+                stack = list
+                    .split(',')
+                    .filter(|w| !w.trim().is_empty())
+                    .map(|w| w.trim().parse())
+                    .collect::<Result<Vec<u32>, _>>()
+                    .map_err(|_| bad("bad stack word"))?;
+                rest = after;
+            }
+            b"step" | b"pc" | b"word" | b"sp" => {
+                let (value, len) = number(rest);
+                let value = value.ok_or_else(|| bad("expected a number"))?;
+                match &key[..] {
+                    b"step" => step = Some(value),
+                    b"pc" => pc = Some(value),
+                    b"word" => word = Some(value),
+                    _ => sp = Some(value),
+                }
+                rest = &rest[len..];
+            }
+            _ => return Err(bad("unknown key")),
+        }
+
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after;
+        } else if rest.strip_prefix('}').is_some_and(|r| r.trim().is_empty()) {
+            break;
+        } else {
+            return Err(bad("expected ',' or '}'"));
+        }
+    }
+
+    let missing = || bad("missing field");
+    let word = u32::try_from(word.ok_or_else(missing)?).map_err(|_| bad("bad word"))?;
+    let step = u64::try_from(step.ok_or_else(missing)?).map_err(|_| bad("bad step"))?;
+    let mut record = record(
+        step,
+        pc.ok_or_else(missing)?,
+        word,
+        sp.ok_or_else(missing)?,
+        stack,
+        output,
+    )?;
+    record.location = location;
+    Ok(record)
+}
+
+// Builds a parsed record, converting byte addresses back to word indexes
+fn record(
+    step: u64,
+    pc: i64,
+    word: u32,
+    sp: i64,
+    stack: Vec<u32>,
+    output: Vec<u8>,
+) -> Result<TraceRecord, String> {
+    let instruction = Instruction::decode(word).map_err(|e| e.to_string())?;
+
+    Ok(TraceRecord {
+        step,
+        pc: (pc.div_euclid(4)) as i32,
+        word,
+        instruction,
+        sp: (sp.div_euclid(4)) as i32,
+        stack,
+        output,
+        location: None,
+    })
+}
+
+/// Where two traces first disagree
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Position in both traces of the first differing record
+    pub index: usize,
+    /// The last record both traces agree on
+    pub previous: Option<TraceRecord>,
+    /// The differing records, `None` if that trace already ended
+    pub left: Option<TraceRecord>,
+    pub right: Option<TraceRecord>,
+    /// Output both runs produced before they diverged
+    pub output: Vec<u8>,
+}
+
+/// Compares two traces record by record and returns the first place they differ.
+/// Step numbers and locations are ignored, so traces that start counting
+/// differently or come from builds with and without symbols still line up.
+pub fn diff(left: &[TraceRecord], right: &[TraceRecord]) -> Option<Divergence> {
+    let same = |a: &TraceRecord, b: &TraceRecord| {
+        a.pc == b.pc
+            && a.word == b.word
+            && a.sp == b.sp
+            && a.stack == b.stack
+            && a.output == b.output
+    };
+
+    let index = (0..left.len().max(right.len())).find(|&i| match (left.get(i), right.get(i)) {
+        (Some(a), Some(b)) => !same(a, b),
+        _ => true,
+    })?;
+
+    Some(Divergence {
+        index,
+        previous: index.checked_sub(1).map(|i| left[i].clone()),
+        left: left.get(index).cloned(),
+        right: right.get(index).cloned(),
+        output: left[..index]
+            .iter()
+            .flat_map(|r| r.output.iter().copied())
+            .collect(),
+    })
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "traces diverge at instruction {}", self.index)?;
+
+        if let Some(prev) = &self.previous {
+            writeln!(f, "  last agreed: {}", summary(prev))?;
+
+            // Different pcs right after a branch means it went different ways
+            if let (Some(l), Some(r)) = (&self.left, &self.right) {
+                if l.pc != r.pc && prev.instruction.branch_offset().is_some() {
+                    writeln!(
+                        f,
+                        "  `{}` at 0x{:04x} went to 0x{:04x} on the left but 0x{:04x} on the right",
+                        prev.instruction.mnemonic(),
+                        prev.pc as i64 * 4,
+                        l.pc as i64 * 4,
+                        r.pc as i64 * 4
+                    )?;
+                }
+            }
+        }
+
+        for (side, record) in [("left", &self.left), ("right", &self.right)] {
+            match record {
+                Some(record) => writeln!(f, "  {:<5}        {}", side, summary(record))?,
+                None => writeln!(f, "  {:<5}        (trace ended)", side)?,
+            }
+        }
+
+        write!(f, "  output so far: {}", quote(&self.output))
+    }
+}
+
+// One line describing a record, including what it printed
+fn summary(record: &TraceRecord) -> String {
+    let mut line = record.format(TraceFormat::Text).trim_start().to_string();
+    if let Some(i) = line.find("  ") {
+        // Drop the step number, the index is already shown
+        line = line[i..].trim_start().to_string();
+    }
+    line
+}
+
+/// Writes trace records to a sink as the machine executes
+pub struct Tracer {
+    out: Box<dyn io::Write>,
+    format: TraceFormat,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn io::Write>, format: TraceFormat) -> Self {
+        Tracer { out, format }
+    }
+
+    pub fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        writeln!(self.out, "{}", record.format(self.format))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::Captured;
+    use crate::{asm, MachineBuilder};
+
+    #[test]
+    fn test_format() {
+        let record = TraceRecord {
+            step: 12,
+            pc: 4,
+            word: 0x7fff_fff8,
+            instruction: Instruction::Goto(-8),
+            sp: 1022,
+            stack: vec![0x2a, 0xffff_ffff],
+            output: Vec::new(),
+            location: None,
+        };
+
+        assert_eq!(
+            "      12  0010: 7ffffff8  goto -8              sp 0ff8  [0000002a ffffffff]",
+            record.format(TraceFormat::Text)
+        );
+        assert_eq!(
+            r#"{"step":12,"pc":16,"word":2147483640,"instruction":"goto -8","sp":4088,"stack":[42,4294967295],"output":""}"#,
+            record.format(TraceFormat::Json)
+        );
+
+        let record = TraceRecord {
+            location: Some("loop+4 (sum.asm:7)".to_string()),
+            ..record
+        };
+        assert!(record
+            .format(TraceFormat::Text)
+            .ends_with("[0000002a ffffffff]  # loop+4 (sum.asm:7)"));
+        assert!(record
+            .format(TraceFormat::Json)
+            .ends_with(r#""output":"","location":"loop+4 (sum.asm:7)"}"#));
+    }
+
+    #[test]
+    fn test_parse() {
+        let record = TraceRecord {
+            step: 7,
+            pc: 3,
+            word: Instruction::Print(0, 1).encode(),
+            instruction: Instruction::Print(0, 1),
+            sp: 1023,
+            stack: vec![0xffff_fffb],
+            output: b"0x\"]\\\n\x01\xff".to_vec(),
+            location: None,
+        };
+        let located = TraceRecord {
+            location: Some("main+12 (echo.asm:4)".to_string()),
+            ..record.clone()
+        };
+
+        for format in [TraceFormat::Text, TraceFormat::Json] {
+            for record in [&record, &located] {
+                let line = record.format(format);
+                assert_eq!(*record, TraceRecord::parse(&line).unwrap(), "{}", line);
+            }
+        }
+
+        assert!(TraceRecord::parse("garbage").is_err());
+        assert!(TraceRecord::parse(r#"{"step":1}"#).is_err());
+    }
+
+    // Runs `source` with `input` and returns its trace
+    fn run_traced(source: &str, input: &str) -> Vec<TraceRecord> {
+        let trace = Captured::default();
+        let mut machine = MachineBuilder::new()
+            .input(io::Cursor::new(input.as_bytes().to_vec()))
+            .output(Vec::new())
+            .trace(trace.clone(), TraceFormat::Json)
+            .build();
+
+        machine.load(&asm::assemble(source).unwrap()).unwrap();
+        machine.run().unwrap();
+
+        let text = String::from_utf8(trace.take()).unwrap();
+        parse_trace(&text).unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let source = "push 1\nprint\nifmi negative\nexit 0\nnegative:\nexit 1";
+        let left = run_traced(source, "");
+
+        assert_eq!(None, diff(&left, &run_traced(source, "")));
+
+        // Another machine takes the branch even though the stack is the same
+        let mut right = left.clone();
+        right[3].pc = 4;
+        right[3].word = Instruction::Exit(1).encode();
+
+        let divergence = diff(&left, &right).unwrap();
+        assert_eq!(3, divergence.index);
+        assert_eq!(b"1\n", &divergence.output[..]);
+
+        let report = divergence.to_string();
+        assert!(
+            report.contains("traces diverge at instruction 3"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("`ifmi` at 0x0008 went to 0x000c on the left but 0x0010 on the right"),
+            "{}",
+            report
+        );
+        assert!(report.contains(r#"output so far: "1\n""#), "{}", report);
+
+        let report = diff(&left, &left[..2]).unwrap().to_string();
+        assert!(report.contains("right        (trace ended)"), "{}", report);
+    }
+}
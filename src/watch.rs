@@ -0,0 +1,200 @@
+// Watchpoints: report every instruction that reads or writes a particular word
+//
+// A watchpoint names a word of ram by index. Stack slots are watched by
+// resolving `sp + 4` against the sp at the time the watchpoint is set, so the
+// watchpoint stays on that word as the stack moves. Instruction fetches and
+// `dump` don't count as reads.
+
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+/// The kind of access a watchpoint triggers on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Either a read or a write
+    ReadWrite,
+}
+
+impl Access {
+    /// Whether a watchpoint on `self` triggers for `access`
+    pub fn includes(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::ReadWrite => write!(f, "access"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// Word index into ram
+    pub index: usize,
+    pub access: Access,
+}
+
+/// One instruction touching a watched word. An instruction that both reads and
+/// writes the word (`swap`, `add` over its left operand) is reported once, as
+/// a write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchHit {
+    /// Word index into ram
+    pub index: usize,
+    /// `Read` or `Write`
+    pub access: Access,
+    pub old: u32,
+    /// The same as `old` for a read
+    pub new: u32,
+    /// Word index of the instruction
+    pub pc: i32,
+    /// sp when the instruction started
+    pub sp: i32,
+    pub instruction: Instruction,
+}
+
+// `write 0x0ff8 (sp + 8): 0x00000005 -> 0x0000000a at 0x0020: swap    0 8`
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} 0x{:04x}", self.access, self.index * 4)?;
+        if self.index as i64 >= self.sp as i64 {
+            write!(f, " (sp + {})", (self.index as i64 - self.sp as i64) * 4)?;
+        }
+        match self.access {
+            Access::Write => write!(f, ": 0x{:08x} -> 0x{:08x}", self.old, self.new)?,
+            _ => write!(f, ": 0x{:08x}", self.old)?,
+        }
+        write!(f, " at 0x{:04x}: {}", self.pc as i64 * 4, self.instruction)
+    }
+}
+
+/// Where a watchpoint goes, as written by the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// A byte address
+    Address(i64),
+    /// A byte offset from sp, `sp + 4` or `sp - 4`
+    Slot(i64),
+}
+
+impl Location {
+    /// Parses `0x0ff8`, `4088`, `sp + 8`, `sp-4` and the like. Offsets and
+    /// addresses are in bytes and have to be a multiple of 4.
+    pub fn parse(text: &str) -> Result<Location, String> {
+        let text = text.trim();
+        let (location, bytes) = match text.strip_prefix("sp") {
+            Some(rest) => {
+                let rest: String = rest.split_whitespace().collect();
+                let bytes = match rest.strip_prefix('+') {
+                    _ if rest.is_empty() => Some(0),
+                    Some(offset) => parse_number(offset),
+                    None => rest.strip_prefix('-').and_then(parse_number).map(|n| -n),
+                };
+                let bytes = bytes.ok_or_else(|| format!("bad stack offset in '{}'", text))?;
+                (Location::Slot(bytes), bytes)
+            }
+            None => {
+                let bytes = parse_number(text)
+                    .filter(|&bytes| bytes >= 0)
+                    .ok_or_else(|| {
+                        format!("expected an address or sp + <offset>, got '{}'", text)
+                    })?;
+                (Location::Address(bytes), bytes)
+            }
+        };
+
+        if bytes % 4 != 0 {
+            return Err(format!("'{}' is not a multiple of 4 bytes", text));
+        }
+        Ok(location)
+    }
+
+    /// Word index of the location given the current sp, if it is in memory
+    pub fn index(self, sp: i32, memory_words: usize) -> Result<usize, String> {
+        let index = match self {
+            Location::Address(bytes) => bytes / 4,
+            Location::Slot(bytes) => sp as i64 + bytes / 4,
+        };
+        match usize::try_from(index) {
+            Ok(index) if index < memory_words => Ok(index),
+            _ => Err(format!("{} is not a word in memory", self)),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Location::Address(bytes) => write!(f, "0x{:04x}", bytes),
+            Location::Slot(bytes) if bytes < 0 => write!(f, "sp - {}", -bytes),
+            Location::Slot(bytes) => write!(f, "sp + {}", bytes),
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location() {
+        assert_eq!(Ok(Location::Address(0xff8)), Location::parse("0xff8"));
+        assert_eq!(Ok(Location::Slot(8)), Location::parse("sp + 8"));
+        assert_eq!(Ok(Location::Slot(-4)), Location::parse("sp-4"));
+        assert_eq!(Ok(Location::Slot(0)), Location::parse("sp"));
+        assert!(Location::parse("sp + 2").is_err());
+        assert!(Location::parse("6").is_err());
+        assert!(Location::parse("-4").is_err());
+        assert!(Location::parse("sp * 4").is_err());
+
+        assert_eq!(Ok(1022), Location::Slot(8).index(1020, 1024));
+        assert_eq!(Ok(1023), Location::Slot(-4).index(1024, 1024));
+        assert_eq!(
+            Err("sp + 16 is not a word in memory".to_string()),
+            Location::Slot(16).index(1020, 1024)
+        );
+        assert!(Location::Address(4096).index(1024, 1024).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let hit = WatchHit {
+            index: 1022,
+            access: Access::Write,
+            old: 5,
+            new: 10,
+            pc: 8,
+            sp: 1020,
+            instruction: Instruction::Swap(0, 2),
+        };
+        assert_eq!(
+            "write 0x0ff8 (sp + 8): 0x00000005 -> 0x0000000a at 0x0020: swap    0 8",
+            hit.to_string()
+        );
+
+        let hit = WatchHit {
+            access: Access::Read,
+            sp: 1023,
+            ..hit
+        };
+        assert_eq!(
+            "read 0x0ff8: 0x00000005 at 0x0020: swap    0 8",
+            hit.to_string()
+        );
+    }
+}
@@ -0,0 +1,515 @@
+// Differential tests against marz/machine, the reference implementation
+//
+// Every marz/*.v fixture and a set of generated programs run through both the
+// reference binary and ours with the same stdin, and stdout, stderr and the exit
+// status are compared. A run that faults is compared only by its stdout, since
+// the reference panics (status 101) where we report a VM error (status 125).
+//
+// Every case has to agree, except the ones in `KNOWN_DIVERGENCES`, which have to
+// differ in exactly the way recorded there.
+// The reference is a Linux ELF, so elsewhere the test only prints a note.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cosc365_machine::asm;
+
+/// A known divergence: program, stdin and difference
+type Known = (&'static str, &'static str, &'static str);
+
+/// Cases where we knowingly differ from the reference, grouped by why. Each is
+/// the program (a fixture, or a generated program's name), its stdin and the
+/// difference `compare` reports.
+const KNOWN_DIVERGENCES: &[(&str, &[Known])] = &[
+    (
+        "the reference treats swap's operands as byte offsets into word memory",
+        &[
+            ("marz/calc.v", "1\n2\n3\n4\n5\n6\n", r#"stdout at byte 171: "" vs "5\n\n\n0 - exit\n1 - add\n2 -""#),
+            ("marz/swap.v", "", "status Exited(20) vs Exited(0)"),
+            ("marz/swap.v", "5\n3\n", "status Exited(20) vs Exited(0)"),
+            ("marz/swap.v", "-7\n2\n", "status Exited(20) vs Exited(0)"),
+            ("marz/swap.v", "1\n2\n3\n4\n5\n6\n", "status Exited(20) vs Exited(0)"),
+            ("swap-0", "", r#"stdout at byte 0: "33554432\n0\n1\n" vs "2\n3\n1\n""#),
+            ("swap-1", "", r#"stdout at byte 1: "31072\n0\n1\n" vs "\n2\n3\n""#),
+            ("swap-2", "", r#"stdout at byte 1: "96608\n0\n1\n" vs "\n2\n3\n""#),
+            ("swap-3", "", r#"stdout at byte 2: "512\n1\n" vs "1\n2\n""#),
+        ],
+    ),
+    (
+        "the reference compares the top of the stack against the word below",
+        &[
+            ("marz/for.v", "5\n3\n", r#"stdout at byte 27: "" vs "i = 2\ni = 3\ni = 4\ni = 5\n""#),
+            ("marz/for.v", "-7\n2\n", "status TimedOut vs Exited(0)"),
+            ("marz/for.v", "1\n2\n3\n4\n5\n6\n", "status TimedOut vs Exited(0)"),
+            ("iflt-0", "", "status Exited(2) vs Exited(1)"),
+            ("iflt-1", "", "status Exited(1) vs Exited(2)"),
+            ("iflt-3", "", "status Exited(1) vs Exited(2)"),
+            ("ifgt-0", "", "status Exited(1) vs Exited(2)"),
+            ("ifgt-1", "", "status Exited(2) vs Exited(1)"),
+            ("ifgt-3", "", "status Exited(2) vs Exited(1)"),
+            ("ifle-0", "", "status Exited(2) vs Exited(1)"),
+            ("ifle-1", "", "status Exited(1) vs Exited(2)"),
+            ("ifle-3", "", "status Exited(1) vs Exited(2)"),
+            ("ifge-0", "", "status Exited(1) vs Exited(2)"),
+            ("ifge-1", "", "status Exited(2) vs Exited(1)"),
+            ("ifge-3", "", "status Exited(2) vs Exited(1)"),
+        ],
+    ),
+    (
+        "we divide unsigned, the reference divides signed",
+        &[
+            ("div-0", "", r#"stdout at byte 0: "0\n" vs "64\n""#),
+            ("div-2", "", r#"stdout at byte 0: "-14\n" vs "893\n""#),
+            ("div-7", "", r#"stdout at byte 0: "-933694\n" vs "0\n""#),
+            ("rem-0", "", r#"stdout at byte 0: "-2\n" vs "2\n""#),
+            ("rem-1", "", r#"stdout at byte 0: "3\n" vs "4665539\n""#),
+            ("rem-2", "", r#"stdout at byte 0: "1\n" vs "545245\n""#),
+            ("rem-4", "", r#"stdout at byte 0: "-6454392\n" vs "4790819\n""#),
+            ("rem-5", "", r#"stdout at byte 0: "3\n" vs "6075767\n""#),
+        ],
+    ),
+    (
+        "the reference panics on a zero divisor",
+        &[
+            ("rem-6", "", "status Faulted vs Exited(0)"),
+        ],
+    ),
+    (
+        "we print NUL bytes, the reference stops at the first one",
+        &[
+            ("stinput-2", "\n", r#"stdout at byte 0: "" vs "\0\0\0""#),
+            ("stprint-4", "", r#"stdout at byte 1: "" vs "\0a""#),
+        ],
+    ),
+    (
+        "the reference prints lowercase hex digits",
+        &[
+            ("marz/print.v", "-7\n2\n", r#"stdout at byte 44: "fffffff9\nIn binary : 0b1" vs "FFFFFFF9\nIn binary : 0b1""#),
+            ("printh-0", "", r#"stdout at byte 2: "fffffff8\n" vs "FFFFFFF8\n""#),
+            ("printh-2", "", r#"stdout at byte 2: "fffffffd\n" vs "FFFFFFFD\n""#),
+        ],
+    ),
+    (
+        "the reference sign extends the immediate from bit 26, not bit 27",
+        &[
+            ("push-0", "", r#"stdout at byte 3: "-1\n" vs "134217727\n""#),
+        ],
+    ),
+    (
+        "we write dumps to stderr and index them by word",
+        &[
+            ("dump-0", "", r#"stdout at byte 0: "0ff8: 00000002\n0ffc: 000" vs """#),
+        ],
+    ),
+    (
+        "we write debug output to stderr, the reference prints the pc, sp and memory bounds to stdout",
+        &[
+            ("marz/debug.v", "", r#"stdout at byte 13: "DEBUG: PC @ 0x0018, SP @" vs """#),
+            ("marz/debug.v", "5\n3\n", r#"stdout at byte 13: "DEBUG: PC @ 0x0018, SP @" vs """#),
+            ("marz/debug.v", "-7\n2\n", r#"stdout at byte 13: "DEBUG: PC @ 0x0018, SP @" vs """#),
+            ("marz/debug.v", "1\n2\n3\n4\n5\n6\n", r#"stdout at byte 13: "DEBUG: PC @ 0x0018, SP @" vs """#),
+            ("debug-0", "", r#"stdout at byte 0: "DEBUG: PC @ 0x0000, SP @" vs """#),
+        ],
+    ),
+];
+
+// Every known divergence as (why, program, stdin, difference)
+fn known_divergences(
+) -> impl Iterator<Item = (&'static str, &'static str, &'static str, &'static str)> {
+    KNOWN_DIVERGENCES.iter().flat_map(|(why, cases)| {
+        cases
+            .iter()
+            .map(move |(program, stdin, difference)| (*why, *program, *stdin, *difference))
+    })
+}
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How a single run ended
+#[derive(Debug, PartialEq)]
+enum Status {
+    Exited(i32),
+    /// Panicked (reference) or stopped with a VM error (ours)
+    Faulted,
+    TimedOut,
+}
+
+#[derive(Debug)]
+struct Run {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    status: Status,
+}
+
+struct Case {
+    /// The fixture, or the instruction kind and a count for generated programs
+    name: String,
+    image: PathBuf,
+    stdin: String,
+}
+
+fn reference_machine() -> Option<PathBuf> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("marz/machine");
+    // Any failure to even start it (wrong OS, missing file) means we can't compare
+    let runs = Command::new(&path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok();
+    runs.then_some(path)
+}
+
+fn run(machine: &Path, image: &Path, stdin: &str, fault_status: i32) -> Run {
+    let mut child = Command::new(machine)
+        .arg(image)
+        .env("RUST_BACKTRACE", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // A program that stops reading early closes the pipe, which is fine
+    let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+
+    let started = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if started.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let timed_out = started.elapsed() > TIMEOUT;
+    let output = child.wait_with_output().unwrap();
+    let status = match output.status.code() {
+        _ if timed_out => Status::TimedOut,
+        Some(code) if code == fault_status => Status::Faulted,
+        Some(code) => Status::Exited(code),
+        None => Status::Faulted,
+    };
+
+    Run {
+        stdout: output.stdout,
+        stderr: output.stderr,
+        status,
+    }
+}
+
+// Why two runs of the same case disagree, or None if they agree
+fn compare(reference: &Run, ours: &Run) -> Option<String> {
+    if reference.status != ours.status {
+        return Some(format!(
+            "status {:?} vs {:?}",
+            reference.status, ours.status
+        ));
+    }
+    if reference.stdout != ours.stdout {
+        return Some(format!(
+            "stdout {}",
+            first_difference(&reference.stdout, &ours.stdout)
+        ));
+    }
+    // Fault messages are never going to match
+    if reference.status != Status::Faulted && reference.stderr != ours.stderr {
+        return Some(format!(
+            "stderr {}",
+            first_difference(&reference.stderr, &ours.stderr)
+        ));
+    }
+    None
+}
+
+// Where two outputs part ways and a little of each from there, e.g.
+// `at byte 13: "0xfffffff9\n" vs "0xFFFFFFF9\n"`
+fn first_difference(reference: &[u8], ours: &[u8]) -> String {
+    const SHOWN: usize = 24;
+    let at = reference
+        .iter()
+        .zip(ours)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let rest = |bytes: &[u8]| {
+        String::from_utf8_lossy(&bytes[at..(at + SHOWN).min(bytes.len())]).into_owned()
+    };
+    format!("at byte {}: {:?} vs {:?}", at, rest(reference), rest(ours))
+}
+
+// Tiny deterministic generator so the generated programs are the same every run
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> i32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as i32
+    }
+
+    // A value that is often small, sometimes negative and sometimes at either end
+    // of what a push means the same to both machines (see push-0)
+    fn value(&mut self) -> i32 {
+        match self.next().rem_euclid(4) {
+            0 => self.next().rem_euclid(10),
+            1 => -self.next().rem_euclid(10),
+            2 => [0, 1, -1, 0x3ff_ffff, -0x400_0000][self.next().rem_euclid(5) as usize],
+            _ => self.next() >> 8,
+        }
+    }
+}
+
+// Generated programs: the instruction kind they exercise, source and stdin
+fn generated_programs() -> Vec<(String, String, String)> {
+    let mut rng = Lcg(365);
+    let mut programs = Vec::new();
+    let mut add = |kind: &str, source: String, stdin: &str| {
+        programs.push((kind.to_string(), source, stdin.to_string()));
+    };
+
+    for op in [
+        "add", "sub", "mul", "div", "rem", "and", "or", "xor", "lsl", "lsr", "asr",
+    ] {
+        for _ in 0..8 {
+            let (a, b) = (rng.value(), rng.value());
+            add(
+                op,
+                format!("push {}\npush {}\n{}\nprint\nexit\n", a, b, op),
+                "",
+            );
+        }
+    }
+
+    // Shift amounts of 32 and up, and negative ones
+    for op in ["lsl", "lsr", "asr"] {
+        for (a, b) in [(1, 32), (1, 40), (-8, 33), (-1, -1)] {
+            add(
+                op,
+                format!("push {}\npush {}\n{}\nprint\nexit\n", a, b, op),
+                "",
+            );
+        }
+    }
+    // i32::MIN, built without a push that needs more than 27 bits
+    add(
+        "neg",
+        "push 1\npush 31\nlsl\nneg\nprint\nexit\n".to_string(),
+        "",
+    );
+
+    for op in ["neg", "not"] {
+        for _ in 0..4 {
+            add(
+                op,
+                format!("push {}\n{}\nprint\nexit\n", rng.value(), op),
+                "",
+            );
+        }
+    }
+
+    for op in ["ifeq", "ifne", "iflt", "ifgt", "ifle", "ifge"] {
+        for (a, b) in [(5, 3), (3, 5), (4, 4), (-1, 2)] {
+            let source = format!("push {}\npush {}\n{} yes\nexit 1\nyes:\nexit 2\n", a, b, op);
+            add(op, source, "");
+        }
+    }
+
+    for op in ["ifez", "ifnz", "ifmi", "ifpl"] {
+        for a in [0, 3, -3] {
+            add(
+                op,
+                format!("push {}\n{} yes\nexit 1\nyes:\nexit 2\n", a, op),
+                "",
+            );
+        }
+    }
+
+    for op in ["print", "printh", "printb", "printo"] {
+        for offset in [0, 4, 8] {
+            let source = format!(
+                "push {}\npush {}\npush {}\n{} {}\nexit\n",
+                rng.value(),
+                rng.value(),
+                rng.value(),
+                op,
+                offset
+            );
+            add(op, source, "");
+        }
+    }
+
+    for (from, to) in [(4, 0), (8, 0), (0, 8), (4, 8)] {
+        let source = format!(
+            "push 1\npush 2\npush 3\nswap {} {}\nprint 0\nprint 4\nprint 8\nexit\n",
+            from, to
+        );
+        add("swap", source, "");
+    }
+
+    for offset in [0, 4, 8] {
+        let source = format!("push 1\npush 2\npush 3\ndup {}\nprint\nexit\n", offset);
+        add("dup", source, "");
+    }
+
+    for offset in [0, 4, 8, 12] {
+        let source = format!("push 1\npush 2\npush 3\npop {}\nprint\nexit\n", offset);
+        add("pop", source, "");
+    }
+
+    for code in [0, 1, 42, 255] {
+        add("exit", format!("exit {}\n", code), "");
+    }
+
+    for stdin in ["12\n", "-12\n", "0x1f\n", "0b101\n"] {
+        add("input", "input\nprint\nexit\n".to_string(), stdin);
+    }
+
+    for (max, stdin) in [
+        ("", "hello world\n"),
+        ("3", "hello\n"),
+        ("", "\n"),
+        ("2", "aé\n"),
+    ] {
+        add(
+            "stinput",
+            format!("stinput {}\nstprint\nexit\n", max),
+            stdin,
+        );
+    }
+
+    for s in ["a", "ab", "abc", "hello, world\\n"] {
+        add("stprint", format!("stpush \"{}\"\nstprint\nexit\n", s), "");
+    }
+    // A NUL in the middle of a word
+    add("stprint", "push 0x610062\nstprint\nexit\n".to_string(), "");
+
+    add(
+        "call",
+        "push 9\ncall f\nprint\nexit\nf:\nreturn 0\n".to_string(),
+        "",
+    );
+    add(
+        "return",
+        "call f\nexit 3\nf:\npush 7\nreturn 4\n".to_string(),
+        "",
+    );
+    add("goto", "goto end\nexit 1\nend:\nexit 2\n".to_string(), "");
+    add("nop", "nop\nnop\nexit 4\n".to_string(), "");
+    add(
+        "push",
+        "push -1\nprint\npush 0x7ffffff\nprint\nexit\n".to_string(),
+        "",
+    );
+    add("dump", "push 1\npush 2\ndump\nexit\n".to_string(), "");
+    add("debug", "debug 0xdead\nexit\n".to_string(), "");
+
+    programs
+}
+
+fn cases(dir: &Path) -> Vec<Case> {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("marz");
+    let mut names: Vec<PathBuf> = std::fs::read_dir(&fixtures)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "v"))
+        .collect();
+    names.sort();
+
+    let mut cases = Vec::new();
+    for image in names {
+        let name = format!("marz/{}", image.file_name().unwrap().to_string_lossy());
+        for stdin in ["", "5\n3\n", "-7\n2\n", "1\n2\n3\n4\n5\n6\n"] {
+            cases.push(Case {
+                name: name.clone(),
+                image: image.clone(),
+                stdin: stdin.to_string(),
+            });
+        }
+    }
+
+    // Named by kind and how many of that kind came before, e.g. `div-3`
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for (kind, source, stdin) in generated_programs() {
+        let count = counts.entry(kind.clone()).or_default();
+        let name = format!("{}-{}", kind, count);
+        *count += 1;
+
+        let image = dir.join(format!("{}.v", name));
+        let program = asm::assemble(&source).unwrap();
+        std::fs::write(&image, asm::to_bytes(&program)).unwrap();
+        cases.push(Case { name, image, stdin });
+    }
+
+    cases
+}
+
+#[test]
+fn differential_against_reference() {
+    let Some(reference) = reference_machine() else {
+        eprintln!("marz/machine doesn't run on this platform, skipping differential tests");
+        return;
+    };
+    let ours = PathBuf::from(env!("CARGO_BIN_EXE_cosc365-machine"));
+
+    let dir = std::env::temp_dir().join(format!("cosc365-differential-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut unexpected = Vec::new();
+    let mut seen = Vec::new();
+    for case in cases(&dir) {
+        let expected = run(&reference, &case.image, &case.stdin, 101);
+        let actual = run(&ours, &case.image, &case.stdin, 125);
+        let difference = compare(&expected, &actual);
+
+        let known = known_divergences()
+            .find(|(_, program, stdin, _)| *program == case.name && *stdin == case.stdin);
+        if let Some((_, program, stdin, _)) = known {
+            seen.push((program, stdin));
+        }
+        let describe = |what: &str| {
+            format!(
+                "{} with stdin {:?} ({}): {}",
+                case.name,
+                case.stdin,
+                case.image.display(),
+                what
+            )
+        };
+        match (known, difference) {
+            (None, None) => (),
+            (Some((why, _, _, expected)), Some(difference)) if expected == difference => {
+                eprintln!("{}", describe(&format!("known: {}", why)));
+            }
+            (Some(_), None) => unexpected.push(describe(
+                "now agrees with the reference, drop it from KNOWN_DIVERGENCES",
+            )),
+            (Some((_, _, _, expected)), Some(difference)) => unexpected.push(describe(&format!(
+                "{}, KNOWN_DIVERGENCES expects {}",
+                difference, expected
+            ))),
+            (None, Some(difference)) => unexpected.push(describe(&difference)),
+        }
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+
+    for (_, program, stdin, _) in known_divergences() {
+        if !seen.contains(&(program, stdin)) {
+            unexpected.push(format!(
+                "{} with stdin {:?}: no such case, drop it from KNOWN_DIVERGENCES",
+                program, stdin
+            ));
+        }
+    }
+
+    assert!(
+        unexpected.is_empty(),
+        "unexpected differences from marz/machine:\n{}",
+        unexpected.join("\n")
+    );
+}